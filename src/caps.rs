@@ -0,0 +1,119 @@
+use std::{fmt::Debug, fs::File, path::Path};
+
+use anyhow::{Context, Result};
+use evdev::Device;
+
+/* Print what an input device can report: its id, relative axes, absolute
+ * axes with their ranges and keys/buttons, by symbolic name. The device is
+ * opened read-only and never grabbed, so this is safe on a device in use.
+ */
+pub fn print(path: &Path, json: bool) -> Result<()> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open input device {}", path.display()))?;
+    let device = Device::from_fd(file.into())
+        .with_context(|| format!("{} is not an evdev input device", path.display()))?;
+
+    let relative = names(device.supported_relative_axes().into_iter().flatten());
+    let keys = names(device.supported_keys().into_iter().flatten());
+    let absolute: Vec<(String, evdev::AbsInfo)> = device
+        .get_absinfo()
+        .with_context(|| format!("Failed to read absolute axes of {}", path.display()))?
+        .map(|(axis, info)| (format!("{axis:?}"), info))
+        .collect();
+    let id = device.input_id();
+    let name = device.name().unwrap_or("Unknown device");
+
+    if json {
+        let absolute: Vec<String> = absolute
+            .iter()
+            .map(|(axis, info)| {
+                format!(
+                    "{{\"axis\":\"{axis}\",\"min\":{},\"max\":{},\"fuzz\":{},\"flat\":{},\
+                     \"resolution\":{}}}",
+                    info.minimum(),
+                    info.maximum(),
+                    info.fuzz(),
+                    info.flat(),
+                    info.resolution()
+                )
+            })
+            .collect();
+        println!(
+            "{{\"path\":{},\"name\":{},\"id\":{{\"bus\":\"{}\",\"vendor\":{},\"product\":{},\
+             \"version\":{}}},\"relative\":[{}],\"absolute\":[{}],\"keys\":[{}]}}",
+            json_string(&path.display().to_string()),
+            json_string(name),
+            id.bus_type(),
+            id.vendor(),
+            id.product(),
+            id.version(),
+            quoted(&relative),
+            absolute.join(","),
+            quoted(&keys)
+        );
+        return Ok(());
+    }
+
+    println!("Device:   {} ({name})", path.display());
+    println!(
+        "Id:       bus {} vendor {:04x} product {:04x} version {:04x}",
+        id.bus_type(),
+        id.vendor(),
+        id.product(),
+        id.version()
+    );
+    println!("Relative: {}", list(&relative));
+    if absolute.is_empty() {
+        println!("Absolute: none");
+    } else {
+        println!("Absolute:");
+        for (axis, info) in &absolute {
+            println!(
+                "  {axis} min {} max {} fuzz {} flat {} resolution {}",
+                info.minimum(),
+                info.maximum(),
+                info.fuzz(),
+                info.flat(),
+                info.resolution()
+            );
+        }
+    }
+    println!("Keys:     {}", list(&keys));
+    Ok(())
+}
+
+fn names<T: Debug>(codes: impl Iterator<Item = T>) -> Vec<String> {
+    codes.map(|code| format!("{code:?}")).collect()
+}
+
+fn list(names: &[String]) -> String {
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        names.join(" ")
+    }
+}
+
+// Symbolic code names need no escaping.
+fn quoted(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("\"{name}\""))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// `value` as a JSON string literal.
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
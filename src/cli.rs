@@ -0,0 +1,613 @@
+use std::{env, os::fd::RawFd, path::PathBuf, process, str::FromStr, time::Duration};
+
+use anyhow::{Context, Result, bail};
+use evdev::{AbsoluteAxisCode, EventType, KeyCode, RelativeAxisCode, SynchronizationCode};
+use log::LevelFilter;
+
+use crate::{
+    hid::{Config, Filter, ReportQuirk},
+    keyboard::KEYBOARD_GADGET_PATH,
+    remap::{Action, Axis},
+};
+
+const USAGE: &str = "\
+Usage: hidex [OPTIONS]
+       hidex caps <PATH> [--json]
+       hidex gadgets
+       hidex keyboard <PATH> [--gadget <PATH>] [-v|-vv|-q]
+
+Options:
+  --input <PATH>            Input device to forward instead of picking one in the TUI
+  --fd <N>                  Forward an inherited, already open evdev fd
+  --input-fifo <PATH>       Forward raw 5-byte reports from a FIFO, bypassing evdev
+  --output <PATH>           Write raw 5-byte reports to a file or FIFO instead of the
+                            gadget; - is stdout, uinput a local virtual mouse
+  --gadget <PATH>           HID gadget node to write to (default: /dev/hidg1)
+  --scroll-step <N>         Wheel units emitted per detent, fractions allowed (default: 1)
+  --hwheel-sign <1|-1>      Direction of horizontal scroll; -1 flips it (default: 1)
+  --quantize <N|X,Y>        Emit pointer motion in steps of N counts (or X and Y per
+                            axis), carrying the rest (default: 1)
+  --remap <BTN=ACTION>      Bind a button (e.g. BTN_SIDE) to left, right, middle, side,
+                            extra, button6-8, scroll-up, scroll-down, pan-left, pan-right,
+                            wheel:N, hwheel:N or none; repeatable
+  --layer <MOD:BTN=ACTION>  Like --remap, but only while button MOD is held; MOD then
+                            only switches layers; repeatable
+  --scroll-repeat-ms <MS>   Interval of scroll and pan buttons while held and between
+                            wheel:N/hwheel:N detents (default: 50)
+  --debounce-ms <MS>        Merge a release and re-press within MS into one hold
+                            (default: off)
+  --min-hold-ms <MS>        Drop button presses released within MS, delaying the rest
+                            by MS (default: off)
+  --restore-held            Start with the buttons the device reports held instead of
+                            all released, e.g. after a reconnect mid-hold
+  --resume-gap-ms <MS>      Resync buttons after a suspend of at least MS (default: 1000,
+                            0 disables)
+  --panic-chord <BUTTONS>   Buttons (e.g. left+right) whose long hold releases all and
+                            stops forwarding (default: all five, none disables)
+  --panic-hold-ms <MS>      How long the panic chord must be held (default: 2000)
+  --motion-only             Forward pointer X/Y only, no buttons or wheels
+  --buttons-only            Forward buttons and wheels only, no pointer X/Y
+  --ignore-axis <AXES>      Ignore the device's x, y, wheel and/or hwheel events
+                            (comma-separated); repeatable
+  --middle-scroll           Scroll by moving the pointer with the middle button held;
+                            a hold without motion still clicks
+  --middle-scroll-button <BTN>
+                            Button to hold for --middle-scroll instead of BTN_MIDDLE
+  --middle-scroll-scale <F|X,Y>
+                            Detents per motion count while middle-scrolling, or per
+                            X (hwheel) and Y (wheel) count (default: 0.1)
+  --wheel-accel <EXP>       Boost fast wheel flicks by (speed / 10 per s) ^ EXP
+                            (default: 0, off)
+  --wheel-accel-cap <N>     Largest wheel boost factor (default: 8)
+  --dial <WHEEL>            Wheel that REL_DIAL jog dials scroll: wheel, hwheel or none
+                            (default: wheel)
+  --dial-scale <F>          Detents per dial step, fractions allowed (default: 1)
+  --stick                   Move the pointer with a gamepad's analog stick (ABS_X/ABS_Y)
+  --stick-deadzone <F>      Fraction of the stick's range ignored around the centre
+                            (default: 0.1)
+  --stick-speed <N>         Pointer counts per 10 ms at full deflection (default: 8)
+  --burst-on-sync           Send the part of a delta beyond +-127 right away in
+                            back-to-back reports instead of with the next ones
+  --max-carry <N>           Most motion per axis carried beyond one report; the rest is
+                            dropped with a warning (default: 10000, 0 carries nothing)
+  --flush-on <CODES>        Comma-separated sync codes that flush a report: SYN_REPORT,
+                            SYN_MT_REPORT, SYN_CONFIG (default: SYN_REPORT)
+  --tap <CODES>             Log these comma-separated event codes (e.g. REL_WHEEL_HI_RES,
+                            BTN_SIDE) instead of translating them; repeatable
+  --coalesce-window-us <US> Merge motion frames within US into one report (default: off)
+  --match-poll-interval     Coalesce motion frames over the host's polling interval
+                            when it can be found out and no window is set
+  --watchdog-ms <MS>        Warn if input arrives for MS without any report written
+                            (default: off)
+  --watchdog-events <N>     Events within the window that count as input (default: 100)
+  --watchdog-reset          Also reopen the gadget and resync when the watchdog fires
+  --keepalive-ms <MS>       Resend the held buttons after MS without a report, for hosts
+                            that drop a quiet device (default: off)
+  --slow-write-us <US>      Time gadget writes, warn about those taking US or longer and
+                            log a histogram at exit (default: off)
+  --write-timeout-ms <MS>   Queue reports instead of blocking when a write takes longer
+                            than MS (default: 0, block)
+  --report-quirk <QUIRK>    Troubleshooting: reverse-button-bits or reverse-bytes of
+                            every report, for hosts that misread it
+  --no-dedup                Also send motionless reports identical to the previous one
+  --startup-delay <MS>      Wait MS before opening and grabbing the device (default: 0)
+  --wait-gadget <MS>        Wait up to MS for the gadget node to appear (default: 0)
+  --nonblocking             Read the input device nonblocking, always via poll
+  --grab-retries <N>        Retry grabbing a busy device N times (default: 0)
+  --strict                  Refuse a device that sends nothing hidex forwards instead of
+                            warning
+  --once                    Exit after forwarding the first report from the device
+  --no-grab                 Forward without grabbing; local input stays active too
+  --check-descriptor        Warn if the gadget's report descriptor doesn't match the
+                            5-byte mouse report
+  --on-start <CMD>          Shell command to run once forwarding starts
+  --on-stop <CMD>           Shell command to run when forwarding stops
+  --no-udc-check            Skip the startup check that the gadget is bound to a UDC
+  --no-auto-select          Without a terminal, fail instead of forwarding the first
+                            pointer device when no device is given
+  --no-monitor              Forward headless instead of showing the live monitor
+  --socket <PATH>           Serve live stats as JSON lines on a Unix socket
+  --socket-inject           Also accept reports to send on the socket
+  -v, -vv                   Log debug (-v) or trace with report dumps (-vv)
+  -q                        Only log errors
+  -h, --help                Print this help and exit
+
+Commands:
+  caps <PATH> [--json]      Print the capabilities of an input device (axes with
+                            ranges, keys, id) without grabbing it, and exit
+  gadgets                   List the /dev/hidg* nodes and what their report
+                            descriptors declare, and exit
+  keyboard <PATH> [--gadget <PATH>] [-v|-vv|-q]
+                            Forward a keyboard's keys as 8-byte boot keyboard reports
+                            to a keyboard gadget (default: /dev/hidg0)
+
+Logging defaults to RUST_LOG (or info when unset); -v/-vv/-q override it.
+HIDEX_INPUT, HIDEX_OUTPUT, HIDEX_SCROLL_STEP, HIDEX_HWHEEL_SIGN, HIDEX_SOCKET and
+HIDEX_REMAP (comma-separated) set the matching options; flags take precedence.";
+
+// Environment variables standing in for a flag with a value.
+const ENV_FLAGS: [(&str, &str); 5] = [
+    ("HIDEX_INPUT", "--input"),
+    ("HIDEX_OUTPUT", "--output"),
+    ("HIDEX_SCROLL_STEP", "--scroll-step"),
+    ("HIDEX_HWHEEL_SIGN", "--hwheel-sign"),
+    ("HIDEX_SOCKET", "--socket"),
+];
+
+// What to do, as chosen on the command line.
+pub enum Command {
+    Forward(Box<Args>),
+    // Print the capabilities of an input device.
+    Caps {
+        path: PathBuf,
+        json: bool,
+    },
+    // List the HID gadget nodes.
+    Gadgets,
+    // Forward a keyboard to a keyboard gadget.
+    Keyboard {
+        input: PathBuf,
+        gadget: PathBuf,
+        log_level: Option<LevelFilter>,
+    },
+}
+
+// Parsed command line for forwarding.
+pub struct Args {
+    pub config: Config,
+    // Input device to forward, skipping the picker.
+    pub input: Option<PathBuf>,
+    // Already open input device fd to forward.
+    pub fd: Option<RawFd>,
+    // FIFO of pre-encoded reports to forward instead of an input device.
+    pub input_fifo: Option<PathBuf>,
+    // Show the live monitor while forwarding instead of running headless.
+    pub monitor: bool,
+    // Forward the first pointer device when no device is given and there
+    // is no terminal for the picker.
+    pub auto_select: bool,
+    // Introspection socket to serve stats on.
+    pub socket: Option<PathBuf>,
+    // Accept `inject` lines on the socket.
+    pub inject: bool,
+    // Log level from -v/-vv/-q; None leaves it to RUST_LOG.
+    pub log_level: Option<LevelFilter>,
+}
+
+impl Args {
+    // Whether reports go to stdout (`--output -`).
+    pub fn stdout_taken(&self) -> bool {
+        self.config
+            .output
+            .as_ref()
+            .is_some_and(|output| output.as_os_str() == "-")
+    }
+}
+
+/* Parse the process arguments, after the HIDEX_* environment variables.
+ * Prints the usage text and exits on -h/--help.
+ */
+pub fn parse() -> Result<Command> {
+    match env::args().nth(1).as_deref() {
+        Some("caps") => return caps(env::args().skip(2)),
+        Some("gadgets") => return gadgets(env::args().skip(2)),
+        Some("keyboard") => return keyboard(env::args().skip(2)),
+        _ => {}
+    }
+    Ok(Command::Forward(Box::new(forward_args()?)))
+}
+
+// Arguments of `hidex caps`; the HIDEX_* variables don't apply.
+fn caps(args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut path = None;
+    let mut json = false;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                process::exit(0);
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(PathBuf::from(arg)),
+            _ => bail!("Unknown argument '{arg}' for caps\n\n{USAGE}"),
+        }
+    }
+    let path =
+        path.context("caps needs an input device path, e.g. hidex caps /dev/input/event3")?;
+    Ok(Command::Caps { path, json })
+}
+
+// Arguments of `hidex gadgets`: none but --help.
+fn gadgets(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    match args.next().as_deref() {
+        None => Ok(Command::Gadgets),
+        Some("-h" | "--help") => {
+            println!("{USAGE}");
+            process::exit(0);
+        }
+        Some(arg) => bail!("Unknown argument '{arg}' for gadgets\n\n{USAGE}"),
+    }
+}
+
+// Arguments of `hidex keyboard`; the HIDEX_* variables don't apply.
+fn keyboard(mut args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut input = None;
+    let mut gadget = PathBuf::from(KEYBOARD_GADGET_PATH);
+    let mut log_level = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--gadget" => gadget = value(&mut args, &arg)?,
+            "-v" | "-vv" | "-q" => log_level = verbosity(&arg, log_level),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                process::exit(0);
+            }
+            _ if input.is_none() && !arg.starts_with('-') => input = Some(PathBuf::from(arg)),
+            _ => bail!("Unknown argument '{arg}' for keyboard\n\n{USAGE}"),
+        }
+    }
+    let input = input
+        .context("keyboard needs an input device path, e.g. hidex keyboard /dev/input/event2")?;
+    Ok(Command::Keyboard {
+        input,
+        gadget,
+        log_level,
+    })
+}
+
+fn forward_args() -> Result<Args> {
+    // Environment values go first so flags on the command line override them.
+    let env = env_args(|var| env::var(var).ok());
+    forward_args_from(env.into_iter().chain(env::args().skip(1)))
+}
+
+fn forward_args_from(mut args: impl Iterator<Item = String>) -> Result<Args> {
+    let mut config = Config::default();
+    let mut input = None;
+    let mut input_fifo = None;
+    let mut fd = None;
+    let mut monitor = true;
+    let mut auto_select = true;
+    let mut socket = None;
+    let mut inject = false;
+    let mut log_level = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => input = Some(value(&mut args, &arg)?),
+            "--fd" => fd = Some(value(&mut args, &arg)?),
+            "--input-fifo" => input_fifo = Some(value(&mut args, &arg)?),
+            "--output" => config.output = Some(value(&mut args, &arg)?),
+            "--gadget" => config.gadget = value(&mut args, &arg)?,
+            "--scroll-step" => config.scroll_step = positive(&arg, value(&mut args, &arg)?)?,
+            "--hwheel-sign" => {
+                config.hwheel_sign = value(&mut args, &arg)?;
+                if config.hwheel_sign.abs() != 1 {
+                    bail!("--hwheel-sign must be 1 or -1");
+                }
+            }
+            "--quantize" => config.quantize = quantize(&value::<String>(&mut args, &arg)?)?,
+            "--remap" => config.remap.add(&value::<String>(&mut args, &arg)?)?,
+            "--layer" => config.remap.add_layer(&value::<String>(&mut args, &arg)?)?,
+            "--scroll-repeat-ms" => {
+                config.scroll_repeat = Duration::from_millis(value(&mut args, &arg)?)
+            }
+            "--debounce-ms" => config.debounce = Duration::from_millis(value(&mut args, &arg)?),
+            "--restore-held" => config.restore_held = true,
+            "--min-hold-ms" => config.min_hold = Duration::from_millis(value(&mut args, &arg)?),
+            "--resume-gap-ms" => config.resume_gap = Duration::from_millis(value(&mut args, &arg)?),
+            "--panic-chord" => config.panic_chord = chord(&value::<String>(&mut args, &arg)?)?,
+            "--panic-hold-ms" => config.panic_hold = Duration::from_millis(value(&mut args, &arg)?),
+            "--motion-only" => set_filter(&mut config, Filter::MotionOnly)?,
+            "--buttons-only" => set_filter(&mut config, Filter::ButtonsOnly)?,
+            "--ignore-axis" => ignore_axes(&mut config, &value::<String>(&mut args, &arg)?)?,
+            "--middle-scroll" => config.middle_scroll = true,
+            "--middle-scroll-button" => {
+                config.middle_scroll_button = value(&mut args, &arg)?;
+                config.middle_scroll = true;
+            }
+            "--middle-scroll-scale" => {
+                config.middle_scroll_scale = scroll_scale(&value::<String>(&mut args, &arg)?)?
+            }
+            // 0 is how acceleration is turned off, so it is allowed here.
+            "--wheel-accel" => {
+                config.wheel_accel = value(&mut args, &arg)?;
+                if config.wheel_accel != 0.0 {
+                    config.wheel_accel = positive(&arg, config.wheel_accel)?;
+                }
+            }
+            "--wheel-accel-cap" => {
+                config.wheel_accel_cap = value(&mut args, &arg)?;
+                if !(config.wheel_accel_cap.is_finite() && config.wheel_accel_cap >= 1.0) {
+                    bail!("--wheel-accel-cap must be a number of at least 1");
+                }
+            }
+            "--dial" => config.dial = dial(&value::<String>(&mut args, &arg)?)?,
+            "--dial-scale" => config.dial_scale = positive(&arg, value(&mut args, &arg)?)?,
+            "--stick" => config.stick = true,
+            "--stick-deadzone" => {
+                config.stick_deadzone = value(&mut args, &arg)?;
+                if !(0.0..1.0).contains(&config.stick_deadzone) {
+                    bail!("--stick-deadzone must be at least 0 and below 1");
+                }
+            }
+            "--stick-speed" => config.stick_speed = positive(&arg, value(&mut args, &arg)?)?,
+            "--burst-on-sync" => config.burst_on_sync = true,
+            "--max-carry" => {
+                config.max_carry = value(&mut args, &arg)?;
+                if config.max_carry < 0 {
+                    bail!("--max-carry must be 0 or more");
+                }
+            }
+            "--tap" => config
+                .tap
+                .extend(tap_codes(&value::<String>(&mut args, &arg)?)?),
+            "--flush-on" => config.flush_on = sync_codes(&value::<String>(&mut args, &arg)?)?,
+            "--match-poll-interval" => config.match_poll_interval = true,
+            "--coalesce-window-us" => {
+                config.coalesce_window = Duration::from_micros(value(&mut args, &arg)?)
+            }
+            "--keepalive-ms" => config.keepalive = Duration::from_millis(value(&mut args, &arg)?),
+            "--watchdog-ms" => {
+                config.watchdog_window = Duration::from_millis(value(&mut args, &arg)?)
+            }
+            "--watchdog-events" => config.watchdog_events = value(&mut args, &arg)?,
+            "--watchdog-reset" => config.watchdog_reset = true,
+            "--slow-write-us" => config.slow_write = Duration::from_micros(value(&mut args, &arg)?),
+            "--write-timeout-ms" => {
+                config.write_timeout = Duration::from_millis(value(&mut args, &arg)?)
+            }
+            "--report-quirk" => {
+                config.report_quirk = report_quirk(&value::<String>(&mut args, &arg)?)?
+            }
+            "--no-dedup" => config.dedup = false,
+            "--startup-delay" => {
+                config.startup_delay = Duration::from_millis(value(&mut args, &arg)?)
+            }
+            "--wait-gadget" => config.wait_gadget = Duration::from_millis(value(&mut args, &arg)?),
+            "--nonblocking" => config.nonblocking = true,
+            "--grab-retries" => config.grab_retries = value(&mut args, &arg)?,
+            "--strict" => config.strict = true,
+            "--once" => config.once = true,
+            "--no-grab" => config.grab = false,
+            "--check-descriptor" => config.check_descriptor = true,
+            "--on-start" => config.on_start = Some(value(&mut args, &arg)?),
+            "--on-stop" => config.on_stop = Some(value(&mut args, &arg)?),
+            "--no-udc-check" => config.check_udc = false,
+            "--no-monitor" => monitor = false,
+            "--no-auto-select" => auto_select = false,
+            "--socket" => socket = Some(value(&mut args, &arg)?),
+            "--socket-inject" => inject = true,
+            // A repeated -v counts like -vv.
+            "-v" | "-vv" | "-q" => log_level = verbosity(&arg, log_level),
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                process::exit(0);
+            }
+            _ => bail!("Unknown argument '{arg}'\n\n{USAGE}"),
+        }
+    }
+
+    if inject && socket.is_none() {
+        bail!("--socket-inject needs --socket");
+    }
+
+    Ok(Args {
+        config,
+        input,
+        input_fifo,
+        fd,
+        monitor,
+        auto_select,
+        socket,
+        inject,
+        log_level,
+    })
+}
+
+/* Flags equivalent to the HIDEX_* environment variables that `var` finds
+ * set. HIDEX_REMAP holds comma-separated --remap specs.
+ */
+fn env_args(var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let mut args = Vec::new();
+    for (name, flag) in ENV_FLAGS {
+        if let Some(value) = var(name) {
+            args.extend([flag.to_string(), value]);
+        }
+    }
+    if let Some(remaps) = var("HIDEX_REMAP") {
+        for spec in remaps.split(',').filter(|spec| !spec.is_empty()) {
+            args.extend(["--remap".to_string(), spec.to_string()]);
+        }
+    }
+    args
+}
+
+// Take and parse the value following `flag`.
+fn value<T>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let raw = args
+        .next()
+        .with_context(|| format!("Missing value for {flag}"))?;
+    raw.parse()
+        .with_context(|| format!("Invalid value '{raw}' for {flag}"))
+}
+
+// `value` if it is a finite number above zero, as scale factors must be.
+fn positive(flag: &str, value: f32) -> Result<f32> {
+    if !(value.is_finite() && value > 0.0) {
+        bail!("{flag} must be a positive number, not {value}");
+    }
+    Ok(value)
+}
+
+// Log level after a -v, -vv or -q flag on top of `level`.
+fn verbosity(flag: &str, level: Option<LevelFilter>) -> Option<LevelFilter> {
+    match flag {
+        "-v" if level == Some(LevelFilter::Debug) => Some(LevelFilter::Trace),
+        "-v" => Some(LevelFilter::Debug),
+        "-vv" => Some(LevelFilter::Trace),
+        _ => Some(LevelFilter::Error),
+    }
+}
+
+// Parse a `left+right+...` button chord into report bits; `none` disables it.
+fn chord(spec: &str) -> Result<u8> {
+    if spec == "none" {
+        return Ok(0);
+    }
+
+    spec.split('+')
+        .try_fold(0, |mask, name| match name.parse()? {
+            Action::Button(bit) => Ok(mask | bit),
+            _ => bail!("'{name}' is not a button"),
+        })
+}
+
+// Parse a comma-separated list of sync codes for --flush-on.
+fn sync_codes(spec: &str) -> Result<Vec<SynchronizationCode>> {
+    spec.split(',')
+        .map(|name| match name.parse() {
+            Ok(code) if code != SynchronizationCode::SYN_DROPPED => Ok(code),
+            _ => bail!(
+                "Unknown sync code '{name}' (expected SYN_REPORT, SYN_MT_REPORT or SYN_CONFIG)"
+            ),
+        })
+        .collect()
+}
+
+// Parse a comma-separated list of REL_*, ABS_*, KEY_* or BTN_* codes for --tap.
+fn tap_codes(spec: &str) -> Result<Vec<(EventType, u16)>> {
+    spec.split(',')
+        .map(|name| {
+            if let Ok(code) = name.parse::<RelativeAxisCode>() {
+                Ok((EventType::RELATIVE, code.0))
+            } else if let Ok(code) = name.parse::<AbsoluteAxisCode>() {
+                Ok((EventType::ABSOLUTE, code.0))
+            } else if let Ok(key) = name.parse::<KeyCode>() {
+                Ok((EventType::KEY, key.code()))
+            } else {
+                bail!("Unknown event code '{name}' (expected e.g. REL_WHEEL_HI_RES or BTN_SIDE)")
+            }
+        })
+        .collect()
+}
+
+// Parse `F` or `X,Y` middle-scroll factors.
+fn scroll_scale(spec: &str) -> Result<(f32, f32)> {
+    let factor = |raw: &str| {
+        let factor = raw.parse().with_context(|| {
+            format!("Invalid --middle-scroll-scale '{raw}' (expected a positive number)")
+        })?;
+        positive("--middle-scroll-scale", factor)
+    };
+    match spec.split_once(',') {
+        Some((x, y)) => Ok((factor(x)?, factor(y)?)),
+        None => factor(spec).map(|factor| (factor, factor)),
+    }
+}
+
+// Parse `N` or `X,Y` quantization steps.
+fn quantize(spec: &str) -> Result<(i32, i32)> {
+    let step = |raw: &str| match raw.parse() {
+        Ok(step) if (1..=i8::MAX as i32).contains(&step) => Ok(step),
+        _ => bail!("Invalid quantization step '{raw}' (expected an integer from 1 to 127)"),
+    };
+    match spec.split_once(',') {
+        Some((x, y)) => Ok((step(x)?, step(y)?)),
+        None => step(spec).map(|step| (step, step)),
+    }
+}
+
+fn dial(name: &str) -> Result<Option<Axis>> {
+    match name {
+        "wheel" => Ok(Some(Axis::Wheel)),
+        "hwheel" => Ok(Some(Axis::HWheel)),
+        "none" => Ok(None),
+        _ => bail!("Unknown dial target '{name}' (expected wheel, hwheel or none)"),
+    }
+}
+
+fn report_quirk(name: &str) -> Result<ReportQuirk> {
+    match name {
+        "none" => Ok(ReportQuirk::None),
+        "reverse-button-bits" => Ok(ReportQuirk::ReverseButtonBits),
+        "reverse-bytes" => Ok(ReportQuirk::ReverseBytes),
+        _ => bail!("Unknown report quirk '{name}' (expected reverse-button-bits or reverse-bytes)"),
+    }
+}
+
+// Turn off forwarding of each axis in a comma-separated list for --ignore-axis.
+fn ignore_axes(config: &mut Config, spec: &str) -> Result<()> {
+    for axis in spec.split(',') {
+        match axis {
+            "x" => config.forward_x = false,
+            "y" => config.forward_y = false,
+            "wheel" => config.forward_wheel = false,
+            "hwheel" => config.forward_hwheel = false,
+            _ => bail!("Unknown axis '{axis}' (expected x, y, wheel or hwheel)"),
+        }
+    }
+    Ok(())
+}
+
+fn set_filter(config: &mut Config, filter: Filter) -> Result<()> {
+    if config.filter != Filter::All && config.filter != filter {
+        bail!("--motion-only and --buttons-only are mutually exclusive");
+    }
+    config.filter = filter;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_factors_must_be_positive_and_finite() {
+        assert_eq!(positive("--scroll-step", 0.5).unwrap(), 0.5);
+        assert_eq!(positive("--scroll-step", 3.0).unwrap(), 3.0);
+        for value in [0.0, -1.0, f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert!(
+                positive("--scroll-step", value).is_err(),
+                "{value} accepted"
+            );
+        }
+    }
+
+    // Scroll step parsed from these environment variables, then `flags`.
+    fn scroll_step(env: &[(&str, &str)], flags: &[&str]) -> f32 {
+        let env = env_args(|name| {
+            env.iter()
+                .find(|&&(var, _)| var == name)
+                .map(|&(_, value)| value.to_string())
+        });
+        let flags = flags.iter().map(|flag| flag.to_string());
+        forward_args_from(env.into_iter().chain(flags))
+            .unwrap()
+            .config
+            .scroll_step
+    }
+
+    #[test]
+    fn flags_override_environment_overrides_defaults() {
+        assert_eq!(scroll_step(&[], &[]), 1.0);
+        assert_eq!(scroll_step(&[("HIDEX_SCROLL_STEP", "2")], &[]), 2.0);
+        assert_eq!(
+            scroll_step(&[("HIDEX_SCROLL_STEP", "2")], &["--scroll-step", "3"]),
+            3.0
+        );
+    }
+
+    #[test]
+    fn middle_scroll_scale_takes_one_or_two_positive_factors() {
+        assert_eq!(scroll_scale("0.5").unwrap(), (0.5, 0.5));
+        assert_eq!(scroll_scale("0.2,1").unwrap(), (0.2, 1.0));
+        for spec in ["0", "-1", "inf", "1,inf", "NaN,1", "x", "1,"] {
+            assert!(scroll_scale(spec).is_err(), "{spec} accepted");
+        }
+    }
+}
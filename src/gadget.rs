@@ -0,0 +1,322 @@
+use std::{
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::report::REPORT_LEN;
+
+// Root of the configfs USB gadget tree.
+const CONFIGFS_GADGETS: &str = "/sys/kernel/config/usb_gadget";
+
+// Where hidg nodes live.
+const DEV_DIR: &str = "/dev";
+
+// USB device controllers, with the speed their link came up at.
+const UDC_CLASS: &str = "/sys/class/udc";
+
+// bInterval f_hid uses when the function has no `interval` attribute.
+const DEFAULT_INTERVAL_FULL_SPEED: u32 = 10;
+const DEFAULT_INTERVAL_HIGH_SPEED: u32 = 4;
+
+// Report descriptor short item prefixes (tag and type, size bits masked).
+const ITEM_INPUT: u8 = 0x80;
+const ITEM_USAGE_PAGE: u8 = 0x04;
+const ITEM_REPORT_SIZE: u8 = 0x74;
+const ITEM_REPORT_ID: u8 = 0x84;
+const ITEM_REPORT_COUNT: u8 = 0x94;
+const ITEM_USAGE: u8 = 0x08;
+
+const USAGE_PAGE_GENERIC_DESKTOP: u32 = 0x01;
+const USAGE_PAGE_CONSUMER: u32 = 0x0c;
+const USAGE_MOUSE: u32 = 0x02;
+const USAGE_JOYSTICK: u32 = 0x04;
+const USAGE_GAMEPAD: u32 = 0x05;
+const USAGE_KEYBOARD: u32 = 0x06;
+
+// A hidg node and what its report descriptor says it is.
+#[derive(PartialEq)]
+pub struct GadgetEntry {
+    pub path: PathBuf,
+    // E.g. "mouse, 5-byte reports"; None if the descriptor can't be read.
+    pub descriptor: Option<String>,
+}
+
+// Every /dev/hidg* node, sorted by path.
+pub fn scan_gadgets() -> io::Result<Vec<GadgetEntry>> {
+    let mut gadgets = Vec::new();
+    for entry in fs::read_dir(DEV_DIR)? {
+        let path = entry?.path();
+        if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("hidg"))
+        {
+            let descriptor = describe(&path);
+            gadgets.push(GadgetEntry { path, descriptor });
+        }
+    }
+    gadgets.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(gadgets)
+}
+
+/* What kind of device the configfs hid function behind `hid_path`
+ * declares, from the first application usage of its report descriptor,
+ * and how long its reports are.
+ */
+pub fn describe(hid_path: &Path) -> Option<String> {
+    let function = find_function(hid_path)?;
+    let descriptor = fs::read(function.join("report_desc")).ok()?;
+
+    let mut usage_page = 0;
+    let mut kind = "unknown device";
+    for (tag, data) in items(&descriptor) {
+        match tag {
+            ITEM_USAGE_PAGE => usage_page = data,
+            ITEM_USAGE => {
+                kind = match (usage_page, data) {
+                    (USAGE_PAGE_GENERIC_DESKTOP, USAGE_MOUSE) => "mouse",
+                    (USAGE_PAGE_GENERIC_DESKTOP, USAGE_KEYBOARD) => "keyboard",
+                    (USAGE_PAGE_GENERIC_DESKTOP, USAGE_JOYSTICK) => "joystick",
+                    (USAGE_PAGE_GENERIC_DESKTOP, USAGE_GAMEPAD) => "gamepad",
+                    (USAGE_PAGE_CONSUMER, _) => "consumer control",
+                    _ => continue,
+                };
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    Some(match fs::read_to_string(function.join("report_length")) {
+        Ok(length) => format!("{kind}, {}-byte reports", length.trim()),
+        Err(_) => kind.to_string(),
+    })
+}
+
+// Whether the gadget behind a hidg node is attached to a UDC.
+pub enum UdcState {
+    Bound,
+    Unbound(PathBuf),
+    Unknown,
+}
+
+// Whether a hidg node is there for us to write reports to.
+#[cfg(feature = "tui")]
+#[derive(PartialEq, Eq)]
+pub enum NodeState {
+    Ready,
+    Missing,
+    NotWritable,
+}
+
+#[cfg(feature = "tui")]
+pub fn probe_node(hid_path: &Path) -> NodeState {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    if !hid_path.exists() {
+        return NodeState::Missing;
+    }
+    let Ok(path) = CString::new(hid_path.as_os_str().as_bytes()) else {
+        return NodeState::Missing;
+    };
+    if unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0 {
+        NodeState::Ready
+    } else {
+        NodeState::NotWritable
+    }
+}
+
+/* Best-effort check of the gadget that owns `hid_path`.
+ * Finds the configfs hid function whose `dev` attribute matches the node's
+ * major:minor and reads the owning gadget's `UDC` attribute. Anything we
+ * can't resolve (no configfs, legacy g_hid module, ...) yields Unknown.
+ */
+pub fn probe_udc(hid_path: &Path) -> UdcState {
+    // functions/hid.N -> the gadget directory
+    let Some(gadget) = find_function(hid_path).and_then(|function| {
+        let gadget = function.parent()?.parent()?;
+        Some(gadget.to_path_buf())
+    }) else {
+        return UdcState::Unknown;
+    };
+
+    match fs::read_to_string(gadget.join("UDC")) {
+        Ok(udc) if !udc.trim().is_empty() => UdcState::Bound,
+        Ok(_) => UdcState::Unbound(gadget),
+        Err(_) => UdcState::Unknown,
+    }
+}
+
+/* How often the host polls the hid function behind `hid_path`, from the
+ * function's `interval` attribute (newer kernels) or f_hid's fixed
+ * default, and the speed of the link its UDC is on: bInterval counts
+ * milliseconds at full/low speed and 2^(bInterval - 1) microframes of
+ * 125 us above that. None if the gadget can't be found or is not
+ * connected. Also returns the speed, for messages.
+ */
+pub fn poll_interval(hid_path: &Path) -> Option<(Duration, String)> {
+    let function = find_function(hid_path)?;
+    let gadget = function.parent()?.parent()?;
+    let udc = fs::read_to_string(gadget.join("UDC")).ok()?;
+    let speed = fs::read_to_string(Path::new(UDC_CLASS).join(udc.trim()).join("current_speed"))
+        .ok()?
+        .trim()
+        .to_string();
+    let configured = fs::read_to_string(function.join("interval"))
+        .ok()
+        .and_then(|interval| interval.trim().parse().ok());
+
+    let interval = match speed.as_str() {
+        "low-speed" | "full-speed" => {
+            let frames = configured.unwrap_or(DEFAULT_INTERVAL_FULL_SPEED);
+            Duration::from_millis(frames.into())
+        }
+        "high-speed" | "super-speed" | "super-speed-plus" => {
+            let exponent = configured
+                .unwrap_or(DEFAULT_INTERVAL_HIGH_SPEED)
+                .clamp(1, 16);
+            Duration::from_micros(125 << (exponent - 1))
+        }
+        // UNKNOWN: not connected to a host right now.
+        _ => return None,
+    };
+    Some((interval, speed))
+}
+
+/* Best-effort check that the configfs hid function behind `hid_path`
+ * describes the reports we write: a mouse without report IDs whose input
+ * report is `REPORT_LEN` bytes. Returns what does not fit, or None if the
+ * function can't be found.
+ */
+pub fn descriptor_mismatches(hid_path: &Path) -> Option<Vec<String>> {
+    let function = find_function(hid_path)?;
+    let descriptor = fs::read(function.join("report_desc")).ok()?;
+    let mut problems = Vec::new();
+
+    if let Ok(length) = fs::read_to_string(function.join("report_length"))
+        && length.trim().parse() != Ok(REPORT_LEN)
+    {
+        problems.push(format!(
+            "report_length is {}, expected {REPORT_LEN}",
+            length.trim()
+        ));
+    }
+
+    let mut usage_page = 0;
+    let mut mouse = false;
+    let mut report_id = false;
+    let (mut size, mut count, mut input_bits) = (0, 0, 0);
+    for (tag, data) in items(&descriptor) {
+        match tag {
+            ITEM_USAGE_PAGE => usage_page = data,
+            ITEM_USAGE => mouse |= usage_page == USAGE_PAGE_GENERIC_DESKTOP && data == USAGE_MOUSE,
+            ITEM_REPORT_ID => report_id = true,
+            ITEM_REPORT_SIZE => size = data,
+            ITEM_REPORT_COUNT => count = data,
+            ITEM_INPUT => input_bits += size * count,
+            _ => {}
+        }
+    }
+
+    if !mouse {
+        problems.push("report descriptor does not declare a mouse".to_string());
+    }
+    if report_id {
+        problems.push("report descriptor uses report IDs, hidex writes reports without one".into());
+    }
+    if input_bits != REPORT_LEN as u32 * 8 {
+        problems.push(format!(
+            "input report is {input_bits} bits, expected {}",
+            REPORT_LEN * 8
+        ));
+    }
+
+    Some(problems)
+}
+
+// Short items of a report descriptor as (tag and type, data); long items are skipped.
+fn items(descriptor: &[u8]) -> Vec<(u8, u32)> {
+    let mut items = Vec::new();
+    let mut rest = descriptor;
+
+    while let Some((&prefix, tail)) = rest.split_first() {
+        if prefix == 0xfe {
+            // Long item: size byte, tag byte, data
+            let size = tail.first().copied().unwrap_or(0) as usize;
+            rest = tail.get(2 + size..).unwrap_or_default();
+            continue;
+        }
+
+        let size = match prefix & 0x03 {
+            3 => 4,
+            size => size as usize,
+        };
+        let Some(bytes) = tail.get(..size) else {
+            break;
+        };
+        let data = bytes
+            .iter()
+            .rev()
+            .fold(0, |data, &byte| (data << 8) | byte as u32);
+        items.push((prefix & 0xfc, data));
+        rest = &tail[size..];
+    }
+
+    items
+}
+
+// Locate the configfs hid function directory that exposes `hid_path`.
+fn find_function(hid_path: &Path) -> Option<PathBuf> {
+    let rdev = fs::metadata(hid_path).ok()?.rdev();
+    let wanted = format!("{}:{}", major(rdev), minor(rdev));
+
+    for gadget in fs::read_dir(CONFIGFS_GADGETS).ok()?.flatten() {
+        let Ok(functions) = fs::read_dir(gadget.path().join("functions")) else {
+            continue;
+        };
+
+        for function in functions.flatten() {
+            if !function.file_name().to_string_lossy().starts_with("hid.") {
+                continue;
+            }
+            let dev = fs::read_to_string(function.path().join("dev")).unwrap_or_default();
+            if dev.trim() == wanted {
+                return Some(function.path());
+            }
+        }
+    }
+
+    None
+}
+
+// Same split as glibc's gnu_dev_major/gnu_dev_minor.
+fn major(rdev: u64) -> u64 {
+    ((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff)
+}
+
+fn minor(rdev: u64) -> u64 {
+    (rdev & 0xff) | ((rdev >> 12) & !0xff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_decode_short_and_skip_long_items() {
+        let descriptor = [
+            0x05, 0x01, // Usage Page (Generic Desktop)
+            0x09, 0x02, // Usage (Mouse)
+            0xfe, 0x01, 0x00, 0xaa, // Long item, skipped
+            0x16, 0x01, 0x80, // Logical Minimum (-32767), two bytes
+            0xc0, // End Collection, no data
+            0x75, // Report Size with its data cut off
+        ];
+        assert_eq!(
+            items(&descriptor),
+            [(0x04, 0x01), (0x08, 0x02), (0x14, 0x8001), (0xc0, 0)]
+        );
+    }
+}
@@ -1,41 +1,408 @@
-use std::{fs::OpenOptions, io::Write, path::Path};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    mem,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::fs::OpenOptionsExt,
+    },
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
 
-use anyhow::{Context, Result};
-use evdev::{Device, EventSummary, KeyCode, RelativeAxisCode, SynchronizationCode};
+use anyhow::{Context, Result, bail};
+use evdev::{
+    AbsoluteAxisCode, AttributeSetRef, Device, EventSummary, EventType, InputEvent, KeyCode,
+    PropType, RelativeAxisCode, SynchronizationCode,
+};
+use log::{Level, debug, info, log_enabled, trace, warn};
+use signal_hook::{SigId, consts::SIGUSR1};
 
-// Path to the HID gadget device (mouse).
+use crate::{
+    gadget::{self, UdcState},
+    hooks,
+    inject::Injections,
+    input::{Source, permission_hint},
+    motion::{Motion, MotionPipeline, ScrollCarry, WheelAccel},
+    ratelimit::RateLimiter,
+    remap::{Action, Axis, Remap},
+    report::{BTN_MASK_ALL, REPORT_LEN},
+    stats::Stats,
+    stick::Stick,
+    uinput::UinputSink,
+};
+
+// Default path of the HID gadget device (mouse).
 pub const HID_DEVICE_PATH: &str = "/dev/hidg1";
 
-// Simple 5-byte mouse report:
-// [buttons, x, y, wheel, hwheel]
+// How often a stoppable loop checks its stop flag while idle.
+pub const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Pause between attempts to grab a busy input device.
+const GRAB_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// How often to look for a gadget node that does not exist yet.
+const GADGET_WAIT_INTERVAL: Duration = Duration::from_millis(250);
+
+// Key event value of an autorepeat, as opposed to 0 (release) and 1 (press).
+pub const KEY_REPEAT: i32 = 2;
+
+// Most reports kept back while gadget writes time out; older ones are dropped.
+const BACKLOG_LIMIT: usize = 64;
+
+// Reports dropped within one DROP_LOG_INTERVAL before that is warned about;
+// an occasional drop is only counted in the stats.
+const DROP_WARN_THRESHOLD: u64 = 10;
+
+// Whole frames read from an input FIFO at once.
+const FIFO_BUFFER_FRAMES: usize = 64;
+
+// Least time between warnings about reports dropped from a full backlog,
+// slow writes and other repeated trouble.
+const DROP_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+// How often to say the forwarder is alive until the first event arrives.
+const IDLE_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+// Tunables for the forwarding loop.
+#[derive(Clone)]
+pub struct Config {
+    // Wheel/hwheel units emitted per detent. Fractional steps are carried
+    // across detents, so 0.5 emits one unit every second detent.
+    pub scroll_step: f32,
+    // Multiplier (1 or -1) applied to horizontal scrolling. evdev and HID agree that
+    // positive REL_WHEEL / Wheel scrolls up and positive REL_HWHEEL / AC Pan
+    // scrolls right, so 1 passes both through; -1 fixes hosts that disagree.
+    pub hwheel_sign: i32,
+    // Pointer X/Y are emitted in whole multiples of these counts.
+    pub quantize: (i32, i32),
+    // Grab the input device for exclusive use. Without the grab, local
+    // input stays active alongside the gadget (observe mode).
+    pub grab: bool,
+    // Extra grab attempts when the device is busy.
+    pub grab_retries: u32,
+    // Pause before opening and grabbing the input device.
+    pub startup_delay: Duration,
+    // Refuse devices that send nothing we forward instead of warning.
+    pub strict: bool,
+    // Stop after the first report translated from the device.
+    pub once: bool,
+    // Shell commands run when forwarding starts and stops.
+    pub on_start: Option<String>,
+    pub on_stop: Option<String>,
+    // How long to wait for the gadget node to appear; zero fails right away.
+    pub wait_gadget: Duration,
+    // Open the input fd with O_NONBLOCK; reads that find nothing are retried
+    // after the next poll instead of blocking.
+    pub nonblocking: bool,
+    // Warn at startup if the gadget is not bound to a UDC.
+    pub check_udc: bool,
+    // Warn at startup if the gadget's report descriptor doesn't fit our reports.
+    pub check_descriptor: bool,
+    // A release followed by a re-press of the same button within this
+    // window is forwarded as one continuous hold. Zero disables debouncing.
+    pub debounce: Duration,
+    // Presses released again within this time are never forwarded; longer
+    // ones go out once the time has passed. Zero disables it.
+    pub min_hold: Duration,
+    // Per-button actions overriding the default button layout.
+    pub remap: Remap,
+    // Interval between wheel detents while a scroll-repeat button is held,
+    // and between the detents of a wheel gesture.
+    pub scroll_repeat: Duration,
+    // Suspends at least this long trigger a release-all and a button resync
+    // from the device's key state. Zero disables the check.
+    pub resume_gap: Duration,
+    // Start forwarding with the buttons the device reports as held, e.g.
+    // after picking a reconnected device mid-hold, instead of all released.
+    pub restore_held: bool,
+    // Skip a motionless report identical to the previous write.
+    pub dedup: bool,
+    // Report buttons that, held together for `panic_hold`, release everything
+    // and stop forwarding. Zero disables the chord.
+    pub panic_chord: u8,
+    pub panic_hold: Duration,
+    pub filter: Filter,
+    // Per relative axis of the device: false ignores its events entirely.
+    pub forward_x: bool,
+    pub forward_y: bool,
+    pub forward_wheel: bool,
+    pub forward_hwheel: bool,
+    // Holding `middle_scroll_button` turns pointer motion into scrolling, at
+    // `middle_scroll_scale` detents per X (hwheel) and Y (wheel) count.
+    pub middle_scroll: bool,
+    pub middle_scroll_button: KeyCode,
+    pub middle_scroll_scale: (f32, f32),
+    // Boost fast wheel flicks by (speed / threshold) ^ `wheel_accel`, up to
+    // `wheel_accel_cap` times; zero keeps every detent 1:1.
+    pub wheel_accel: f32,
+    pub wheel_accel_cap: f32,
+    // Wheel that REL_DIAL (jog dials) drives, at `dial_scale` detents per
+    // dial step; None drops the dial.
+    pub dial: Option<Axis>,
+    pub dial_scale: f32,
+    // Drive the pointer from an analog stick (ABS_X/ABS_Y) by velocity:
+    // deflection beyond `stick_deadzone` (a fraction of the range) moves it
+    // at up to `stick_speed` counts per tick.
+    pub stick: bool,
+    pub stick_deadzone: f32,
+    pub stick_speed: f32,
+    // Motion beyond the i8 range always goes out in later reports; this
+    // sends it right away as back-to-back reports of the same frame.
+    pub burst_on_sync: bool,
+    // Most motion per axis carried into later reports; beyond that it is
+    // dropped, so one absurd delta can't turn into thousands of writes.
+    pub max_carry: i32,
+    // Write reports to this file or FIFO (`-` for stdout) instead of the
+    // gadget, as raw 5-byte frames.
+    pub output: Option<PathBuf>,
+    // The hidg node reports go to when there is no `output`.
+    pub gadget: PathBuf,
+    // Hold motion for up to this long after the first frame of a burst and
+    // send it as one report. Zero writes every frame. The poll timeout has
+    // millisecond granularity, so shorter windows round up to 1ms.
+    pub coalesce_window: Duration,
+    // Use the host's polling interval as `coalesce_window` when it is
+    // zero and the interval can be found out.
+    pub match_poll_interval: bool,
+    // Give up on a gadget write after this long and queue the report
+    // instead. Zero blocks until the write completes.
+    pub write_timeout: Duration,
+    // Time each gadget write and warn about those taking at least this
+    // long. Zero skips the measurement.
+    pub slow_write: Duration,
+    pub report_quirk: ReportQuirk,
+    // Warn when `watchdog_events` input events arrive within
+    // `watchdog_window` without a single report written. Zero disables it.
+    pub watchdog_window: Duration,
+    pub watchdog_events: u64,
+    // Also reopen the gadget and resync the buttons when the watchdog fires.
+    pub watchdog_reset: bool,
+    // Resend the held buttons without motion after this long without a
+    // report, for hosts that stop polling a quiet device. Zero disables it.
+    pub keepalive: Duration,
+    // Sync codes that end a frame and flush a report.
+    pub flush_on: Vec<SynchronizationCode>,
+    // Event codes that are only logged, never translated, for studying a device.
+    pub tap: HashSet<(EventType, u16)>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            scroll_step: 1.0,
+            hwheel_sign: 1,
+            quantize: (1, 1),
+            grab: true,
+            grab_retries: 0,
+            startup_delay: Duration::ZERO,
+            strict: false,
+            once: false,
+            on_start: None,
+            on_stop: None,
+            wait_gadget: Duration::ZERO,
+            nonblocking: false,
+            check_udc: true,
+            check_descriptor: false,
+            debounce: Duration::ZERO,
+            min_hold: Duration::ZERO,
+            remap: Remap::default(),
+            scroll_repeat: Duration::from_millis(50),
+            resume_gap: Duration::from_secs(1),
+            restore_held: false,
+            dedup: true,
+            panic_chord: BTN_MASK_ALL,
+            panic_hold: Duration::from_secs(2),
+            filter: Filter::All,
+            forward_x: true,
+            forward_y: true,
+            forward_wheel: true,
+            forward_hwheel: true,
+            middle_scroll: false,
+            middle_scroll_button: KeyCode::BTN_MIDDLE,
+            middle_scroll_scale: (0.1, 0.1),
+            wheel_accel: 0.0,
+            wheel_accel_cap: 8.0,
+            dial: Some(Axis::Wheel),
+            dial_scale: 1.0,
+            stick: false,
+            stick_deadzone: 0.1,
+            stick_speed: 8.0,
+            burst_on_sync: false,
+            max_carry: 10_000,
+            output: None,
+            gadget: PathBuf::from(HID_DEVICE_PATH),
+            coalesce_window: Duration::ZERO,
+            match_poll_interval: false,
+            write_timeout: Duration::ZERO,
+            slow_write: Duration::ZERO,
+            report_quirk: ReportQuirk::None,
+            watchdog_window: Duration::ZERO,
+            keepalive: Duration::ZERO,
+            watchdog_events: 100,
+            watchdog_reset: false,
+            flush_on: vec![SynchronizationCode::SYN_REPORT],
+            tap: HashSet::new(),
+        }
+    }
+}
+
+impl Config {
+    // Settings that change what the host sees, for the startup summary.
+    fn transforms(&self) -> Vec<String> {
+        let mut transforms = Vec::new();
+        if self.scroll_step != 1.0 {
+            transforms.push(format!("scroll-step {}", self.scroll_step));
+        }
+        if self.hwheel_sign != 1 {
+            transforms.push("hwheel inverted".to_string());
+        }
+        if self.quantize != (1, 1) {
+            transforms.push(format!("quantize {}x{}", self.quantize.0, self.quantize.1));
+        }
+        match self.filter {
+            Filter::All => {}
+            Filter::MotionOnly => transforms.push("motion only".to_string()),
+            Filter::ButtonsOnly => transforms.push("buttons only".to_string()),
+        }
+        let ignored: Vec<&str> = [
+            (self.forward_x, "x"),
+            (self.forward_y, "y"),
+            (self.forward_wheel, "wheel"),
+            (self.forward_hwheel, "hwheel"),
+        ]
+        .into_iter()
+        .filter(|&(forwarded, _)| !forwarded)
+        .map(|(_, axis)| axis)
+        .collect();
+        if !ignored.is_empty() {
+            transforms.push(format!("ignore {}", ignored.join(",")));
+        }
+        if !self.remap.is_empty() {
+            transforms.push("remap".to_string());
+        }
+        if !self.debounce.is_zero() {
+            transforms.push(format!("debounce {:?}", self.debounce));
+        }
+        if !self.tap.is_empty() {
+            transforms.push(format!("tap {} code(s)", self.tap.len()));
+        }
+        if !self.min_hold.is_zero() {
+            transforms.push(format!("min hold {:?}", self.min_hold));
+        }
+        if self.middle_scroll {
+            let (x, y) = self.middle_scroll_scale;
+            let button = match self.middle_scroll_button {
+                KeyCode::BTN_MIDDLE => String::new(),
+                button => format!(" on {button:?}"),
+            };
+            if x == y {
+                transforms.push(format!("middle-scroll{button} x{x}"));
+            } else {
+                transforms.push(format!("middle-scroll{button} x{x},{y}"));
+            }
+        }
+        if self.wheel_accel != 0.0 {
+            transforms.push(format!(
+                "wheel accel ^{} cap {}",
+                self.wheel_accel, self.wheel_accel_cap
+            ));
+        }
+        match self.dial {
+            Some(axis) if self.dial_scale != 1.0 => {
+                transforms.push(format!("dial to {axis:?} x{}", self.dial_scale))
+            }
+            Some(Axis::Wheel) => {}
+            Some(axis) => transforms.push(format!("dial to {axis:?}")),
+            None => transforms.push("dial dropped".to_string()),
+        }
+        if self.stick {
+            transforms.push(format!(
+                "stick deadzone {} speed {}",
+                self.stick_deadzone, self.stick_speed
+            ));
+        }
+        if self.burst_on_sync {
+            transforms.push("burst".to_string());
+        }
+        if self.max_carry != Config::default().max_carry {
+            transforms.push(format!("max carry {}", self.max_carry));
+        }
+        if !self.coalesce_window.is_zero() {
+            transforms.push(format!("coalesce {:?}", self.coalesce_window));
+        }
+        if !self.dedup {
+            transforms.push("no dedup".to_string());
+        }
+        match self.report_quirk {
+            ReportQuirk::None => {}
+            ReportQuirk::ReverseButtonBits => transforms.push("button bits reversed".to_string()),
+            ReportQuirk::ReverseBytes => transforms.push("report bytes reversed".to_string()),
+        }
+        transforms
+    }
+}
+
+// Which parts of the input are forwarded; the rest stays zero in reports.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    All,
+    // Pointer X/Y only, for a move-only device.
+    MotionOnly,
+    // Buttons and wheels only, for a click/scroll-only device.
+    ButtonsOnly,
+}
+
+/* Troubleshooting aid for hosts that seem to read the report wrongly:
+ * applied to every encoded report, so quirks can be tried without a rebuild.
+ */
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ReportQuirk {
+    None,
+    // Button 1 in the top bit of the button byte, button 8 in the lowest.
+    ReverseButtonBits,
+    // The five report bytes in reverse order.
+    ReverseBytes,
+}
+
+// Mouse report being built, see `report` for the byte layout.
 #[derive(Default)]
 struct Report {
     buttons: u8,
-    x: i8,
-    y: i8,
-    wheel: i8,
-    hwheel: i8,
+    // Accumulated raw motion; bytes clamp it to the i8 range.
+    motion: Motion,
 }
 
 impl Report {
+    // Take what fits into a report byte out of `value`, leaving the rest.
     #[inline]
-    fn to_bytes(&self) -> [u8; 5] {
-        [
-            self.buttons,
-            self.x as u8,
-            self.y as u8,
-            self.wheel as u8,
-            self.hwheel as u8,
-        ]
+    fn drain_axis(value: &mut i32) -> i8 {
+        let sent = clamp_i8(*value);
+        *value -= sent as i32;
+        sent
     }
 
-    // After each SYN_REPORT we reset relative fields.
     #[inline]
-    fn reset_motion(&mut self) {
-        self.x = 0;
-        self.y = 0;
-        self.wheel = 0;
-        self.hwheel = 0;
+    fn to_bytes(&self, quirk: ReportQuirk) -> [u8; REPORT_LEN] {
+        let mut bytes = [
+            self.buttons,
+            clamp_i8(self.motion.x) as u8,
+            clamp_i8(self.motion.y) as u8,
+            clamp_i8(self.motion.wheel) as u8,
+            clamp_i8(self.motion.hwheel) as u8,
+        ];
+        match quirk {
+            ReportQuirk::None => {}
+            ReportQuirk::ReverseButtonBits => bytes[0] = bytes[0].reverse_bits(),
+            ReportQuirk::ReverseBytes => bytes.reverse(),
+        }
+        bytes
     }
 }
 
@@ -53,68 +420,2275 @@ fn update_button(byte: &mut u8, pressed: bool, mask: u8) {
     }
 }
 
-/* Run the main forwarding loop:
- * - read events from the selected evdev device
- * - convert them into HID mouse reports
- * - write reports to /dev/hidg1
+/* Button presses or releases held back for a delay, per report button bit.
+ * The opposite edge of the same button inside the delay cancels the held
+ * back one. Debouncing defers releases, so the host sees chatter as one
+ * continuous hold; a minimum hold defers presses, so brief touches never
+ * reach the host at all.
  */
-pub fn run_forwarder(input_device: &Path) -> Result<()> {
-    let mut device = Device::open(input_device)
-        .with_context(|| format!("Failed to open input device {}", input_device.display()))?;
+struct Deferred {
+    delay: Duration,
+    // Button state applied once the delay has passed.
+    pressed: bool,
+    // Deadline of the held-back change, per report button bit.
+    pending: [Option<Instant>; 8],
+}
+
+impl Deferred {
+    fn new(delay: Duration, pressed: bool) -> Self {
+        Self {
+            delay,
+            pressed,
+            pending: [None; 8],
+        }
+    }
+
+    // Hold back the change of `mask`; false if there is no delay.
+    fn defer(&mut self, mask: u8, now: Instant) -> bool {
+        if self.delay.is_zero() {
+            return false;
+        }
+        self.pending[mask.trailing_zeros() as usize] = Some(now + self.delay);
+        true
+    }
 
-    // Grab the device so events are consumed only by us.
-    device
-        .grab()
-        .with_context(|| "Failed to grab input device (try running as root)".to_string())?;
+    // Drop a held-back change of `mask`; true if there was one.
+    fn cancel(&mut self, mask: u8) -> bool {
+        self.pending[mask.trailing_zeros() as usize]
+            .take()
+            .is_some()
+    }
 
-    let mut hid = OpenOptions::new()
-        .write(true)
-        .open(HID_DEVICE_PATH)
-        .with_context(|| format!("Failed to open HID gadget at {HID_DEVICE_PATH}"))?;
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pending.iter().flatten().min().copied()
+    }
 
-    let mut report = Report::default();
+    // Apply every change that is due; true if any button changed.
+    fn expire(&mut self, now: Instant, buttons: &mut u8) -> bool {
+        let mut changed = false;
+        for (bit, pending) in self.pending.iter_mut().enumerate() {
+            if pending.is_some_and(|deadline| deadline <= now) {
+                *pending = None;
+                update_button(buttons, self.pressed, 1 << bit);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
 
-    loop {
-        for event in device
-            .fetch_events()
-            .context("Failed to read input events")?
+// A held scroll-repeat button and when it scrolls next.
+struct ScrollRepeat {
+    key: KeyCode,
+    axis: Axis,
+    direction: i32,
+    next: Instant,
+}
+
+/* Middle-button scrolling state. The button's click is held back while it
+ * is down and only sent on release if the hold never scrolled.
+ */
+#[derive(Default)]
+struct MiddleScroll {
+    held: bool,
+    scrolled: bool,
+    x_carry: ScrollCarry,
+    y_carry: ScrollCarry,
+    click: Click,
+    // Report bits the held-back click presses; 0 for a scroll-only button.
+    click_mask: u8,
+}
+
+// Progress of a held-back middle click.
+#[derive(Default, PartialEq, Eq)]
+enum Click {
+    #[default]
+    None,
+    // Press is in the report being built.
+    Pending,
+    // Press has been written; the release goes out on the next timer run.
+    Sent,
+}
+
+// Wheel detents still to send for wheel gesture buttons.
+struct WheelBurst {
+    axis: Axis,
+    remaining: i32,
+    next: Instant,
+}
+
+// Translation state carried between events: the report being built plus
+// whatever outlives a single frame.
+struct Translator {
+    config: Config,
+    report: Report,
+    pipeline: MotionPipeline,
+    // Motion past the i8 range of the last report, owed to the next ones.
+    overflow: Motion,
+    // Releases held back by --debounce-ms.
+    debounce: Deferred,
+    // Presses held back by --min-hold-ms.
+    min_hold: Deferred,
+    scroll_repeat: Option<ScrollRepeat>,
+    wheel_burst: Option<WheelBurst>,
+    // Modifier button of the active remap layer.
+    layer: Option<KeyCode>,
+    // Action each held source button resolved to when it was pressed.
+    held_actions: HashMap<KeyCode, Action>,
+    // Trace of each unhandled event code, summed up per interval.
+    unhandled: HashMap<(EventType, u16), RateLimiter>,
+    // Key event values outside 0..=2 already reported.
+    odd_key_values: HashSet<i32>,
+    // Timestamp of the last event of each tapped code.
+    tapped: HashMap<(EventType, u16), Duration>,
+    // Sync codes already warned about for not flushing.
+    ignored_syncs: HashSet<SynchronizationCode>,
+    // Warnings about events the kernel dropped.
+    syn_dropped: RateLimiter,
+    // Warnings about motion dropped beyond `max_carry`.
+    carry_log: RateLimiter,
+    // Whether any event arrived since the last flush on a sync code.
+    frame_open: bool,
+    middle: MiddleScroll,
+    // Fraction of a detent left over from scaling REL_DIAL.
+    dial_carry: ScrollCarry,
+    // Acceleration of the physical wheels, with --wheel-accel.
+    wheel_accel: Option<[WheelAccel; 2]>,
+    // Analog stick moving the pointer, with --stick.
+    stick: Option<Stick>,
+    // Start of the coalescing window the pending frames belong to.
+    coalesce_since: Option<Instant>,
+    // Buttons of the last flushed report.
+    flushed_buttons: u8,
+    // Since when the emergency chord has been held.
+    chord_since: Option<Instant>,
+    // Buttons held by the last injected report, on top of the device's.
+    injected_buttons: u8,
+    // Buttons of the last encoded report, and press edges since the
+    // counts were last handed to the stats.
+    encoded_buttons: u8,
+    presses: [u64; 8],
+}
+
+impl Translator {
+    fn new(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            report: Report::default(),
+            pipeline: MotionPipeline::new(config),
+            overflow: Motion::default(),
+            debounce: Deferred::new(config.debounce, false),
+            min_hold: Deferred::new(config.min_hold, true),
+            scroll_repeat: None,
+            wheel_burst: None,
+            layer: None,
+            held_actions: HashMap::new(),
+            unhandled: HashMap::new(),
+            odd_key_values: HashSet::new(),
+            tapped: HashMap::new(),
+            ignored_syncs: HashSet::new(),
+            syn_dropped: RateLimiter::new(DROP_LOG_INTERVAL),
+            carry_log: RateLimiter::new(DROP_LOG_INTERVAL),
+            frame_open: false,
+            middle: MiddleScroll::default(),
+            dial_carry: ScrollCarry::default(),
+            wheel_accel: (config.wheel_accel != 0.0).then(|| {
+                [(); 2].map(|()| WheelAccel::new(config.wheel_accel, config.wheel_accel_cap))
+            }),
+            stick: None,
+            coalesce_since: None,
+            flushed_buttons: 0,
+            chord_since: None,
+            injected_buttons: 0,
+            encoded_buttons: 0,
+            presses: [0; 8],
+        }
+    }
+
+    // Apply one input event; returns the report to write once a frame is complete.
+    fn handle(&mut self, event: InputEvent, now: Instant) -> Option<[u8; REPORT_LEN]> {
+        if self
+            .config
+            .tap
+            .contains(&(event.event_type(), event.code()))
         {
-            match event.destructure() {
-                EventSummary::RelativeAxis(_, code, value) => match code {
-                    RelativeAxisCode::REL_X => report.x = clamp_i8(value),
-                    RelativeAxisCode::REL_Y => report.y = clamp_i8(value),
-                    RelativeAxisCode::REL_WHEEL => report.wheel = clamp_i8(value),
-                    RelativeAxisCode::REL_HWHEEL => report.hwheel = clamp_i8(value),
-                    _ => {}
-                },
-
-                EventSummary::Key(_, key, value) => {
-                    let pressed = value == 1;
-                    match key {
-                        KeyCode::BTN_LEFT => update_button(&mut report.buttons, pressed, 0x01),
-                        KeyCode::BTN_RIGHT => update_button(&mut report.buttons, pressed, 0x02),
-                        KeyCode::BTN_MIDDLE => update_button(&mut report.buttons, pressed, 0x04),
-                        KeyCode::BTN_SIDE | KeyCode::BTN_BACK => {
-                            update_button(&mut report.buttons, pressed, 0x08)
-                        }
-                        KeyCode::BTN_EXTRA | KeyCode::BTN_FORWARD => {
-                            update_button(&mut report.buttons, pressed, 0x10)
-                        }
-                        _ => {}
+            self.tap(event);
+            return None;
+        }
+        if event.event_type() != EventType::SYNCHRONIZATION {
+            self.frame_open = true;
+        }
+        let report = &mut self.report;
+        let pointer = self.config.filter != Filter::ButtonsOnly;
+        let buttons = self.config.filter != Filter::MotionOnly;
+
+        match event.destructure() {
+            EventSummary::RelativeAxis(_, code, value) => match code {
+                // Ignored axes, e.g. a spurious hwheel; not even for middle-scroll.
+                RelativeAxisCode::REL_X if !self.config.forward_x => {}
+                RelativeAxisCode::REL_Y if !self.config.forward_y => {}
+                RelativeAxisCode::REL_WHEEL if !self.config.forward_wheel => {}
+                RelativeAxisCode::REL_HWHEEL if !self.config.forward_hwheel => {}
+                RelativeAxisCode::REL_X | RelativeAxisCode::REL_Y if self.middle.held => {
+                    self.scroll_with_motion(code, value)
+                }
+                // Added up rather than assigned, so coalesced frames accumulate.
+                RelativeAxisCode::REL_X if pointer => report.motion.x += value,
+                RelativeAxisCode::REL_Y if pointer => report.motion.y += value,
+                RelativeAxisCode::REL_WHEEL if buttons => {
+                    let detents = self.accelerate(Axis::Wheel, value, event);
+                    self.scroll(Axis::Wheel, detents)
+                }
+                RelativeAxisCode::REL_HWHEEL if buttons => {
+                    let detents = self.accelerate(Axis::HWheel, value, event);
+                    self.scroll(Axis::HWheel, detents)
+                }
+                RelativeAxisCode::REL_DIAL if buttons && let Some(axis) = self.config.dial => {
+                    let detents = self.dial_carry.scale(value, self.config.dial_scale);
+                    self.scroll(axis, detents)
+                }
+                // Handled, but filtered out.
+                RelativeAxisCode::REL_X
+                | RelativeAxisCode::REL_Y
+                | RelativeAxisCode::REL_WHEEL
+                | RelativeAxisCode::REL_HWHEEL => {}
+                RelativeAxisCode::REL_DIAL if self.config.dial.is_some() => {}
+                _ => self.note_unhandled(event, now),
+            },
+
+            EventSummary::AbsoluteAxis(_, code, value)
+                if pointer
+                    && let Some(stick) = &mut self.stick
+                    && stick.set(code, value, now) => {}
+
+            // Autorepeat of a held key: it is still held, nothing changes.
+            EventSummary::Key(_, _, KEY_REPEAT) => {}
+
+            EventSummary::Key(_, key, value)
+                if buttons
+                    && self.config.middle_scroll
+                    && key == self.config.middle_scroll_button =>
+            {
+                let pressed = self.key_pressed(value);
+                self.set_middle_scroll(pressed)
+            }
+
+            EventSummary::Key(_, key, value) if buttons && self.config.remap.is_layer_key(key) => {
+                let pressed = self.key_pressed(value);
+                self.set_layer(key, pressed)
+            }
+
+            EventSummary::Key(_, key, value) if buttons => {
+                let pressed = self.key_pressed(value);
+                match self.key_action(key, pressed) {
+                    Some(Action::Button(mask)) => self.set_button(mask, pressed, now),
+                    Some(Action::ScrollRepeat(axis, direction)) => {
+                        self.set_scroll_repeat(key, axis, direction, pressed, now)
+                    }
+                    Some(Action::Wheel(axis, delta)) if pressed => {
+                        self.start_wheel_burst(axis, delta, now)
                     }
+                    Some(Action::Wheel(..) | Action::Disabled) => {}
+                    None => self.note_unhandled(event, now),
                 }
+            }
+
+            EventSummary::Synchronization(_, sync, _) if self.config.flush_on.contains(&sync) => {
+                // Back-to-back flush codes (e.g. SYN_MT_REPORT then
+                // SYN_REPORT) close the same frame; flush it only once.
+                if !mem::take(&mut self.frame_open) {
+                    return None;
+                }
+                self.debounce.expire(now, &mut self.report.buttons);
+                self.min_hold.expire(now, &mut self.report.buttons);
 
-                EventSummary::Synchronization(_, sync, _)
-                    if sync == SynchronizationCode::SYN_REPORT =>
+                // Within a coalescing window, hold motion-only frames back;
+                // button changes always go out at once.
+                if !self.config.coalesce_window.is_zero()
+                    && self.report.buttons == self.flushed_buttons
                 {
-                    let bytes = report.to_bytes();
-                    hid.write_all(&bytes)
-                        .context("Failed to write HID report")?;
-                    report.reset_motion();
+                    self.coalesce_since.get_or_insert(now);
+                    return None;
+                }
+                return Some(self.flush());
+            }
+
+            /* The kernel's buffer overflowed. evdev resyncs the device state
+             * and normally hides this; when it does come through, a device
+             * flooding the buffer would flood the log too.
+             */
+            EventSummary::Synchronization(_, SynchronizationCode::SYN_DROPPED, _) => {
+                if let Some(count) = self.syn_dropped.hit(now) {
+                    warn!(
+                        "The kernel dropped input events ({count} time(s) since the last warning)"
+                    );
                 }
+            }
+
+            // A frame end we were not told to flush on; unless that is
+            // deliberate, reports will lag behind.
+            EventSummary::Synchronization(_, sync, _) if self.ignored_syncs.insert(sync) => {
+                warn!("Device sent {sync:?}, which is not in --flush-on; not flushing on it");
+            }
+
+            // Buttons, filtered out.
+            EventSummary::Key(..) => {}
+            _ => self.note_unhandled(event, now),
+        }
+
+        None
+    }
+
+    /* Whether a key event's value means pressed. Besides 0 (release), 1
+     * (press) and 2 (autorepeat), synthetic sources may send anything;
+     * any other nonzero value counts as a press, traced once per value.
+     */
+    fn key_pressed(&mut self, value: i32) -> bool {
+        if !(0..=KEY_REPEAT).contains(&value)
+            && log_enabled!(Level::Trace)
+            && self.odd_key_values.insert(value)
+        {
+            trace!("Key event with value {value}, treating it as a press (logged once per value)");
+        }
+        value != 0
+    }
+
+    // Log a tapped event with its kernel timestamp, in place of translating it.
+    fn tap(&mut self, event: InputEvent) {
+        let at = event
+            .timestamp()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let name = match event.destructure() {
+            EventSummary::RelativeAxis(_, code, _) => format!("{code:?}"),
+            EventSummary::AbsoluteAxis(_, code, _) => format!("{code:?}"),
+            EventSummary::Key(_, key, _) => format!("{key:?}"),
+            _ => format!("{:?} code {}", event.event_type(), event.code()),
+        };
+        match self.tapped.insert((event.event_type(), event.code()), at) {
+            Some(last) => info!(
+                "tap {name} = {} at {:.6}s, {:.3}ms after the last one",
+                event.value(),
+                at.as_secs_f64(),
+                at.saturating_sub(last).as_secs_f64() * 1000.0
+            ),
+            None => info!("tap {name} = {} at {:.6}s", event.value(), at.as_secs_f64()),
+        }
+    }
+
+    // Trace event codes we have no use for, each at most once per interval.
+    fn note_unhandled(&mut self, event: InputEvent, now: Instant) {
+        if !log_enabled!(Level::Trace) {
+            return;
+        }
+        let Some(count) = self
+            .unhandled
+            .entry((event.event_type(), event.code()))
+            .or_insert_with(|| RateLimiter::new(DROP_LOG_INTERVAL))
+            .hit(now)
+        else {
+            return;
+        };
+        let name = match event.destructure() {
+            EventSummary::RelativeAxis(_, RelativeAxisCode::REL_DIAL, _) => {
+                "REL_DIAL, forward it with --dial wheel or --dial hwheel".to_string()
+            }
+            EventSummary::RelativeAxis(_, code, _) => format!("{code:?}"),
+            EventSummary::Key(_, key, _) => format!("{key:?}"),
+            _ => format!("{:?} code {}", event.event_type(), event.code()),
+        };
+        trace!("Ignoring unhandled {name} ({count} since it was last logged)");
+    }
+
+    fn set_layer(&mut self, key: KeyCode, pressed: bool) {
+        if pressed {
+            self.layer = Some(key);
+        } else if self.layer == Some(key) {
+            self.layer = None;
+        }
+    }
+
+    /* Resolve a button on press and remember the result, so its release
+     * undoes what the press did even if the layer changed in between.
+     */
+    fn key_action(&mut self, key: KeyCode, pressed: bool) -> Option<Action> {
+        if pressed {
+            let action = self.config.remap.layer_action(self.layer, key);
+            if let Some(action) = action {
+                self.held_actions.insert(key, action);
+            }
+            action
+        } else {
+            self.held_actions
+                .remove(&key)
+                .or_else(|| self.config.remap.action(key))
+        }
+    }
+
+    /* A re-press inside the debounce window continues the hold the host
+     * still sees, so only a fresh press has to last the minimum hold, and a
+     * press dropped for being too short has no release to debounce.
+     */
+    fn set_button(&mut self, mask: u8, pressed: bool, now: Instant) {
+        if pressed {
+            if self.debounce.cancel(mask) {
+                debug!("Debounced chatter on button {mask:#04x}");
+            } else if self.min_hold.defer(mask, now) {
+                return;
+            }
+        } else if self.min_hold.cancel(mask) {
+            debug!("Dropped a press of button {mask:#04x} shorter than the minimum hold");
+            return;
+        } else if self.debounce.defer(mask, now) {
+            return;
+        }
+        update_button(&mut self.report.buttons, pressed, mask);
+    }
+
+    // Scroll once on press and keep scrolling from the timer until release.
+    fn set_scroll_repeat(
+        &mut self,
+        key: KeyCode,
+        axis: Axis,
+        direction: i32,
+        pressed: bool,
+        now: Instant,
+    ) {
+        if pressed {
+            self.scroll(axis, direction);
+            self.scroll_repeat = Some(ScrollRepeat {
+                key,
+                axis,
+                direction,
+                next: now + self.config.scroll_repeat,
+            });
+        } else if self
+            .scroll_repeat
+            .as_ref()
+            .is_some_and(|repeat| repeat.key == key)
+        {
+            self.scroll_repeat = None;
+        }
+    }
+
+    // Start or end a middle-button hold; a hold without scrolling clicks.
+    fn set_middle_scroll(&mut self, pressed: bool) {
+        let middle = &mut self.middle;
+        if pressed {
+            middle.held = true;
+            middle.scrolled = false;
+        } else if middle.held {
+            middle.held = false;
+            // A hold that never scrolled is the button's normal click.
+            let click_mask = match self.config.remap.action(self.config.middle_scroll_button) {
+                Some(Action::Button(mask)) => mask,
+                _ => 0,
+            };
+            if !middle.scrolled && click_mask != 0 {
+                update_button(&mut self.report.buttons, true, click_mask);
+                middle.click = Click::Pending;
+                middle.click_mask = click_mask;
+            }
+        }
+    }
+
+    /* Motion while the scroll button is held: X pans, Y scrolls, each by
+     * its own factor, so diagonal motion scrolls diagonally. Moving the
+     * pointer up (negative REL_Y) scrolls up.
+     */
+    fn scroll_with_motion(&mut self, code: RelativeAxisCode, value: i32) {
+        let middle = &mut self.middle;
+        let (x_scale, y_scale) = self.config.middle_scroll_scale;
+        let units = if code == RelativeAxisCode::REL_X {
+            let units = middle.x_carry.scale(value, x_scale);
+            self.report.motion.hwheel += units;
+            units
+        } else {
+            let units = middle.y_carry.scale(-value, y_scale);
+            self.report.motion.wheel += units;
+            units
+        };
+        middle.scrolled |= units != 0;
+    }
+
+    /* Send the first detent of a wheel gesture now and queue the rest.
+     * Another press on the same wheel adds to what is left; a press on the
+     * other wheel replaces it.
+     */
+    fn start_wheel_burst(&mut self, axis: Axis, delta: i32, now: Instant) {
+        let left = self
+            .wheel_burst
+            .take()
+            .filter(|burst| burst.axis == axis)
+            .map_or(0, |burst| burst.remaining);
+        let remaining = left + delta;
+        if remaining == 0 {
+            return;
+        }
+        let detent = remaining.signum();
+        self.scroll(axis, detent);
+        self.wheel_burst = (remaining != detent).then(|| WheelBurst {
+            axis,
+            remaining: remaining - detent,
+            next: now + self.config.scroll_repeat,
+        });
+    }
+
+    // Detents of a physical wheel after --wheel-accel.
+    fn accelerate(&mut self, axis: Axis, detents: i32, event: InputEvent) -> i32 {
+        let at = event
+            .timestamp()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        match &mut self.wheel_accel {
+            Some([wheel, hwheel]) => match axis {
+                Axis::Wheel => wheel.apply(detents, at),
+                Axis::HWheel => hwheel.apply(detents, at),
+            },
+            None => detents,
+        }
+    }
+
+    // Add detents in evdev's direction on top of whatever this frame has.
+    fn scroll(&mut self, axis: Axis, detents: i32) {
+        match axis {
+            Axis::Wheel => self.report.motion.wheel += detents,
+            Axis::HWheel => self.report.motion.hwheel += detents,
+        }
+    }
+
+    /* Drop all transient state: held buttons, layers, pending timers and
+     * every carry and accumulator, back to how a fresh translator starts.
+     * Returns the release-all report that tells the host.
+     */
+    fn reset(&mut self) -> [u8; REPORT_LEN] {
+        self.report = Report::default();
+        self.pipeline = MotionPipeline::new(&self.config);
+        self.overflow = Motion::default();
+        self.debounce = Deferred::new(self.config.debounce, false);
+        self.min_hold = Deferred::new(self.config.min_hold, true);
+        self.scroll_repeat = None;
+        self.wheel_burst = None;
+        self.layer = None;
+        self.held_actions.clear();
+        self.middle = MiddleScroll::default();
+        self.dial_carry = ScrollCarry::default();
+        if let Some(accel) = &mut self.wheel_accel {
+            *accel = [(); 2]
+                .map(|()| WheelAccel::new(self.config.wheel_accel, self.config.wheel_accel_cap));
+        }
+        if let Some(stick) = &mut self.stick {
+            stick.center();
+        }
+        self.coalesce_since = None;
+        self.flushed_buttons = 0;
+        self.chord_since = None;
+        self.injected_buttons = 0;
+        self.encoded_buttons = 0;
+        self.report.to_bytes(self.config.report_quirk)
+    }
+
+    /* Drop all transient state and rebuild the held buttons from the
+     * device's current key state. Returns a release-all report followed by
+     * the resynced one, so the host forgets anything stale first.
+     */
+    fn resync(&mut self, held: &AttributeSetRef<KeyCode>) -> [[u8; REPORT_LEN]; 2] {
+        let release_all = self.reset();
+
+        for key in held.iter() {
+            if self.config.filter == Filter::MotionOnly {
+                break;
+            }
+            // A held scroll button would start a scroll hold mid-way; leave
+            // it released until it is pressed again.
+            if key == self.config.middle_scroll_button && self.config.middle_scroll {
+                continue;
+            }
+            if self.config.remap.is_layer_key(key) {
+                self.layer = Some(key);
+                continue;
+            }
+            if let Some(Action::Button(mask)) = self.config.remap.action(key) {
+                self.held_actions.insert(key, Action::Button(mask));
+                update_button(&mut self.report.buttons, true, mask);
+            }
+        }
+
+        [release_all, self.flush()]
+    }
+
+    // Track the emergency chord; true once it has been held long enough.
+    fn panic_chord_held(&mut self, now: Instant) -> bool {
+        let chord = self.config.panic_chord;
+        let held = chord != 0 && self.report.buttons & chord == chord;
+
+        match (held, self.chord_since) {
+            (false, _) => {
+                self.chord_since = None;
+                false
+            }
+            (true, None) => {
+                self.chord_since = Some(now);
+                self.config.panic_hold.is_zero()
+            }
+            (true, Some(since)) => now - since >= self.config.panic_hold,
+        }
+    }
+
+    // Earliest time `on_timer` or the emergency chord has work to do.
+    fn next_deadline(&self) -> Option<Instant> {
+        let repeat = self.scroll_repeat.as_ref().map(|repeat| repeat.next);
+        let burst = self.wheel_burst.as_ref().map(|burst| burst.next);
+        let chord = self.chord_since.map(|since| since + self.config.panic_hold);
+        let click = (self.middle.click == Click::Sent).then(Instant::now);
+        let stick = self.stick.as_ref().and_then(Stick::next_deadline);
+        let overflow = (self.overflow != Motion::default()).then(Instant::now);
+        let coalesce = self
+            .coalesce_since
+            .map(|since| since + self.config.coalesce_window);
+        [
+            self.debounce.next_deadline(),
+            self.min_hold.next_deadline(),
+            repeat,
+            burst,
+            chord,
+            click,
+            coalesce,
+            stick,
+            overflow,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    // Run timed work that is due; returns a report if the host needs one.
+    fn on_timer(&mut self, now: Instant) -> Option<[u8; REPORT_LEN]> {
+        let mut changed = self.debounce.expire(now, &mut self.report.buttons);
+        changed |= self.min_hold.expire(now, &mut self.report.buttons);
+
+        if let Some(repeat) = &mut self.scroll_repeat
+            && repeat.next <= now
+        {
+            repeat.next = now + self.config.scroll_repeat;
+            let (axis, direction) = (repeat.axis, repeat.direction);
+            self.scroll(axis, direction);
+            changed = true;
+        }
+
+        if let Some(burst) = &mut self.wheel_burst
+            && burst.next <= now
+        {
+            let (axis, detent) = (burst.axis, burst.remaining.signum());
+            burst.remaining -= detent;
+            burst.next = now + self.config.scroll_repeat;
+            if burst.remaining == 0 {
+                self.wheel_burst = None;
+            }
+            self.scroll(axis, detent);
+            changed = true;
+        }
+
+        if let Some(motion) = self.stick.as_mut().and_then(|stick| stick.tick(now)) {
+            self.report.motion.x += motion.x;
+            self.report.motion.y += motion.y;
+            changed = true;
+        }
+
+        if self
+            .coalesce_since
+            .is_some_and(|since| since + self.config.coalesce_window <= now)
+        {
+            changed = true;
+        }
+
+        // A report's worth of what an oversized one left over.
+        if self.overflow != Motion::default() {
+            changed = true;
+        }
+
+        if self.middle.click == Click::Sent {
+            self.middle.click = Click::None;
+            update_button(&mut self.report.buttons, false, self.middle.click_mask);
+            changed = true;
+        }
+
+        changed.then(|| self.flush())
+    }
 
-                _ => {}
+    /* Merge an injected report and send it right away: its buttons stay
+     * held on top of the device's until the next injection changes them,
+     * its motion is added once. Injections never wait for the device.
+     * Their motion is already in report units, so it joins the carry past
+     * the pipeline instead of being scaled like the device's.
+     */
+    fn inject(&mut self, bytes: [u8; REPORT_LEN]) -> [u8; REPORT_LEN] {
+        self.injected_buttons = bytes[0];
+        self.overflow = self.overflow
+            + Motion {
+                x: bytes[1] as i8 as i32,
+                y: bytes[2] as i8 as i32,
+                wheel: bytes[3] as i8 as i32,
+                hwheel: bytes[4] as i8 as i32,
+            };
+        self.flush()
+    }
+
+    fn flush(&mut self) -> [u8; REPORT_LEN] {
+        if self.middle.click == Click::Pending {
+            self.middle.click = Click::Sent;
+        }
+        self.coalesce_since = None;
+        self.flushed_buttons = self.report.buttons;
+        let motion = self.pipeline.apply(mem::take(&mut self.report.motion));
+        self.encode(motion)
+    }
+
+    // A report repeating what the host last got, without motion.
+    fn idle_report(&self) -> [u8; REPORT_LEN] {
+        Report {
+            buttons: self.flushed_buttons | self.injected_buttons,
+            motion: Motion::default(),
+        }
+        .to_bytes(self.config.report_quirk)
+    }
+
+    // Next report of a burst: motion an oversized frame left over.
+    fn burst(&mut self) -> Option<[u8; REPORT_LEN]> {
+        (self.config.burst_on_sync && self.overflow != Motion::default())
+            .then(|| self.encode(Motion::default()))
+    }
+
+    /* Bytes of a report with the held buttons and `motion`, after what
+     * earlier reports could not carry. What doesn't fit into this one is
+     * kept for the next, so no motion is lost to clamping.
+     */
+    fn encode(&mut self, motion: Motion) -> [u8; REPORT_LEN] {
+        let mut rest = mem::take(&mut self.overflow) + motion;
+        let report = Report {
+            buttons: self.report.buttons | self.injected_buttons,
+            motion: Motion {
+                x: Report::drain_axis(&mut rest.x).into(),
+                y: Report::drain_axis(&mut rest.y).into(),
+                wheel: Report::drain_axis(&mut rest.wheel).into(),
+                hwheel: Report::drain_axis(&mut rest.hwheel).into(),
+            },
+        };
+        let pressed = report.buttons & !self.encoded_buttons;
+        for (bit, count) in self.presses.iter_mut().enumerate() {
+            if pressed & 1 << bit != 0 {
+                *count += 1;
+            }
+        }
+        self.encoded_buttons = report.buttons;
+        let limit = self.config.max_carry;
+        self.overflow = Motion {
+            x: rest.x.clamp(-limit, limit),
+            y: rest.y.clamp(-limit, limit),
+            wheel: rest.wheel.clamp(-limit, limit),
+            hwheel: rest.hwheel.clamp(-limit, limit),
+        };
+        if self.overflow != rest
+            && let Some(count) = self.carry_log.hit(Instant::now())
+        {
+            warn!(
+                "Dropped motion beyond --max-carry {limit} ({count} time(s) since the last \
+                 warning); the device sent an absurd delta"
+            );
+        }
+        report.to_bytes(self.config.report_quirk)
+    }
+}
+
+/* Notices input that never turns into reports: some translation state
+ * swallowing everything, or writes silently going nowhere.
+ */
+struct Watchdog {
+    window: Duration,
+    min_events: u64,
+    // End of the current window and the counters at its start.
+    due: Instant,
+    events: u64,
+    reports: u64,
+}
+
+impl Watchdog {
+    fn new(config: &Config, stats: &Stats) -> Self {
+        Self {
+            window: config.watchdog_window,
+            min_events: config.watchdog_events,
+            due: Instant::now() + config.watchdog_window,
+            events: stats.events.load(Ordering::Relaxed),
+            reports: stats.reports.load(Ordering::Relaxed),
+        }
+    }
+
+    // At the end of each window: true if enough events came in but no reports went out.
+    fn stalled(&mut self, now: Instant, stats: &Stats) -> bool {
+        if now < self.due {
+            return false;
+        }
+        let events = stats.events.load(Ordering::Relaxed);
+        let reports = stats.reports.load(Ordering::Relaxed);
+        let stalled = events - self.events >= self.min_events && reports == self.reports;
+
+        self.due = now + self.window;
+        self.events = events;
+        self.reports = reports;
+        stalled
+    }
+}
+
+/* Measures time spent in system suspend: CLOCK_BOOTTIME keeps counting
+ * while suspended and CLOCK_MONOTONIC does not, so growth of their
+ * difference is time asleep. Plain idle time does not count.
+ */
+struct SuspendClock {
+    offset: Duration,
+}
+
+impl SuspendClock {
+    fn new() -> Self {
+        Self {
+            offset: Self::offset(),
+        }
+    }
+
+    // Time spent suspended since the previous call.
+    fn slept(&mut self) -> Duration {
+        let offset = Self::offset();
+        let slept = offset.saturating_sub(self.offset);
+        self.offset = offset;
+        slept
+    }
+
+    fn offset() -> Duration {
+        read_clock(libc::CLOCK_BOOTTIME).saturating_sub(read_clock(libc::CLOCK_MONOTONIC))
+    }
+}
+
+fn read_clock(clock: libc::clockid_t) -> Duration {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Both clocks always exist on Linux, so this cannot fail.
+    unsafe { libc::clock_gettime(clock, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+// Why forwarding ended without an error.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ForwarderExit {
+    // Stopped on request: the stop flag, the emergency chord or the monitor.
+    Quit,
+    // The user wants to pick another device (from the monitor).
+    #[cfg(feature = "tui")]
+    Reselect,
+    // The input device was unplugged.
+    DeviceGone,
+}
+
+// Input device and gadget opened and ready to forward.
+pub struct Forwarder {
+    device: Device,
+    hid: Gadget,
+    config: Config,
+    injections: Option<Injections>,
+    // The input device, as shown to hooks.
+    input: String,
+    // Set by SIGUSR1 (or the monitor) to ask for a soft reset.
+    reset: Arc<AtomicBool>,
+    reset_signal: SigId,
+}
+
+impl Forwarder {
+    /* Open and grab the input device and open the HID gadget.
+     * Done up front so setup errors and warnings surface before any TUI
+     * takes over the terminal. Setting `stop` ends the waits in between.
+     */
+    pub fn open(source: &Source, config: &Config, stop: &AtomicBool) -> Result<Self> {
+        // Let whatever else claims input devices at boot settle first.
+        if !config.startup_delay.is_zero() {
+            info!(
+                "Waiting {:?} before grabbing {source}",
+                config.startup_delay
+            );
+            if !sleep_unless_stopped(config.startup_delay, Some(stop)) {
+                bail!("Stopped before grabbing {source}");
+            }
+        }
+
+        let mut device = source.open()?;
+        if config.nonblocking {
+            device
+                .set_nonblocking(true)
+                .context("Failed to make the input device nonblocking")?;
+        }
+
+        describe_device(&device);
+        if !translatable(&device, config) {
+            let problem = format!(
+                "{source} ({}) sends nothing hidex forwards with these options (no pointer \
+                 motion, wheel or mapped button); is it the right device?",
+                device.name().unwrap_or("Unknown device")
+            );
+            if config.strict {
+                bail!("{problem}");
+            }
+            warn!("{problem}");
+        }
+
+        // Grab the device so events are consumed only by us.
+        if config.grab {
+            grab(&mut device, source, config.grab_retries)?;
+        } else {
+            warn!("Not grabbing the input device; it keeps driving the local system as well");
+        }
+
+        let hid = Gadget::open(config, Some(stop))?;
+
+        // Reports faster than the host polls only queue up in the gadget.
+        let mut config = config.clone();
+        if config.match_poll_interval && config.coalesce_window.is_zero() {
+            match hid.poll_interval {
+                Some(interval) => config.coalesce_window = interval,
+                None => warn!("--match-poll-interval: polling interval unknown, not coalescing"),
             }
         }
+
+        // One line with everything in effect, for a glance and for bug reports.
+        let transforms = config.transforms();
+        info!(
+            "Forwarding {source} ({}) to {} as a {REPORT_LEN}-byte mouse report, {}, \
+             transforms: {}",
+            device.name().unwrap_or("Unknown device"),
+            hid.target,
+            if config.grab {
+                "grabbed"
+            } else {
+                "not grabbed"
+            },
+            if transforms.is_empty() {
+                "none".to_string()
+            } else {
+                transforms.join(", ")
+            }
+        );
+
+        let reset = Arc::new(AtomicBool::new(false));
+        let reset_signal = signal_hook::flag::register(SIGUSR1, Arc::clone(&reset))
+            .context("Failed to install the SIGUSR1 handler")?;
+
+        Ok(Self {
+            device,
+            hid,
+            config,
+            reset,
+            reset_signal,
+            injections: None,
+            input: source.to_string(),
+        })
+    }
+
+    // Send every injected report that has arrived, in order.
+    fn forward_injections(&mut self, translator: &mut Translator, stats: &Stats) -> Result<()> {
+        let Some(injections) = &self.injections else {
+            return Ok(());
+        };
+        while let Some(bytes) = injections
+            .try_recv()
+            .context("Failed to receive injected report")?
+        {
+            let bytes = translator.inject(bytes);
+            self.hid.write_report(bytes, stats)?;
+            while let Some(bytes) = translator.burst() {
+                self.hid.write_report(bytes, stats)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Where reports are written, for display.
+    #[cfg(feature = "tui")]
+    pub fn target(&self) -> &str {
+        &self.hid.target
+    }
+
+    // Setting the returned flag asks the running forwarder for a soft reset.
+    #[cfg(feature = "tui")]
+    pub fn reset_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.reset)
+    }
+
+    // Release everything on the host and rebuild the held buttons from the device.
+    fn resync(&mut self, translator: &mut Translator, stats: &Stats) -> Result<()> {
+        let held = self
+            .device
+            .get_key_state()
+            .context("Failed to read key state")?;
+        for bytes in translator.resync(&held) {
+            self.hid.write_report(bytes, stats)?;
+        }
+        Ok(())
+    }
+
+    // Also forward reports sent through the other end of `injections`.
+    pub fn set_injections(&mut self, injections: Injections) {
+        self.injections = Some(injections);
+    }
+
+    /* Run the main forwarding loop:
+     * - read events from the selected evdev device
+     * - convert them into HID mouse reports
+     * - write reports to the gadget
+     * Returns once `stop` is set; without a stop flag it runs until an error.
+     * The --on-start and --on-stop hooks run around it, while the device is
+     * still grabbed.
+     */
+    pub fn run(&mut self, stats: &Stats, stop: Option<&AtomicBool>) -> Result<ForwarderExit> {
+        if let Some(command) = &self.config.on_start {
+            hooks::run("start", command, &self.hook_env("start"));
+        }
+        let result = self.forward(stats, stop);
+        if let Some(command) = &self.config.on_stop {
+            hooks::run("stop", command, &self.hook_env("stop"));
+        }
+        result
+    }
+
+    // What hooks learn about this forwarder, on top of hidex's environment.
+    fn hook_env<'a>(&'a self, event: &'a str) -> [(&'a str, &'a str); 3] {
+        [
+            ("HIDEX_EVENT", event),
+            ("HIDEX_DEVICE", &self.input),
+            ("HIDEX_TARGET", &self.hid.target),
+        ]
+    }
+
+    fn forward(&mut self, stats: &Stats, stop: Option<&AtomicBool>) -> Result<ForwarderExit> {
+        let mut translator = Translator::new(&self.config);
+        if self.config.stick {
+            translator.stick = Stick::from_device(&self.device, &self.config);
+            if translator.stick.is_none() {
+                warn!("--stick: the device has no ABS_X/ABS_Y stick, ignoring it");
+            }
+        }
+        if self.config.restore_held {
+            self.resync(&mut translator, stats)?;
+            if translator.report.buttons != 0 {
+                info!(
+                    "Restored held buttons {:#04x} from the device",
+                    translator.report.buttons
+                );
+            }
+        }
+        let mut suspend_clock = (!self.config.resume_gap.is_zero()).then(SuspendClock::new);
+        // Cleared for good once the device has produced anything.
+        let mut heartbeat = Some(Instant::now() + IDLE_HEARTBEAT_INTERVAL);
+        let mut watchdog =
+            (!self.config.watchdog_window.is_zero()).then(|| Watchdog::new(&self.config, stats));
+
+        loop {
+            // Everything sent since the last pass, before blocking again.
+            stats.add_presses(mem::take(&mut translator.presses));
+            if stop.is_some_and(|stop| stop.load(Ordering::Relaxed)) {
+                return Ok(ForwarderExit::Quit);
+            }
+
+            // Asked to start over, e.g. with motion wedged by a carry.
+            if self.reset.swap(false, Ordering::Relaxed) {
+                info!("Soft reset: releasing all buttons and clearing all accumulated state");
+                let release_all = translator.reset();
+                self.hid.write_report(release_all, stats)?;
+            }
+
+            // After a suspend the host may still think buttons are held.
+            if let Some(clock) = &mut suspend_clock {
+                let slept = clock.slept();
+                if slept >= self.config.resume_gap {
+                    info!("Resumed after {slept:.1?} asleep, resyncing button state");
+                    self.resync(&mut translator, stats)?;
+                }
+            }
+
+            // Input keeps coming but nothing reaches the host.
+            if let Some(watchdog) = &mut watchdog
+                && watchdog.stalled(Instant::now(), stats)
+            {
+                warn!(
+                    "Watchdog: {} input events in {:?} but no report written",
+                    watchdog.min_events, watchdog.window
+                );
+                if self.config.watchdog_reset {
+                    warn!("Watchdog: reopening the gadget and resyncing button state");
+                    self.hid = Gadget::open(&self.config, stop)?;
+                    self.resync(&mut translator, stats)?;
+                }
+            }
+
+            let now = Instant::now();
+            if let Some(due) = &mut heartbeat
+                && *due <= now
+            {
+                info!(
+                    "Waiting for input from {}",
+                    self.device.name().unwrap_or("Unknown device")
+                );
+                *due = now + IDLE_HEARTBEAT_INTERVAL;
+            }
+
+            if let Some(bytes) = translator.on_timer(now) {
+                self.hid.write_report(bytes, stats)?;
+                while let Some(bytes) = translator.burst() {
+                    self.hid.write_report(bytes, stats)?;
+                }
+            }
+
+            // Nothing went out for a while; keep the host polling.
+            if let Some(due) = self.hid.keepalive_due(self.config.keepalive)
+                && due <= now
+            {
+                trace!(
+                    "Idle for {:?}, sending a keepalive report",
+                    self.config.keepalive
+                );
+                self.hid.write_keepalive(translator.idle_report(), stats)?;
+            }
+
+            // Escape hatch for a grabbed device nobody can otherwise get back.
+            if translator.panic_chord_held(now) {
+                warn!("Emergency chord held, releasing all buttons and stopping");
+                self.hid
+                    .write_report(Report::default().to_bytes(self.config.report_quirk), stats)?;
+                return Ok(ForwarderExit::Quit);
+            }
+
+            // Only poll when there is a deadline or a stop flag to honour;
+            // otherwise block in read().
+            let timeout = [
+                translator
+                    .next_deadline()
+                    .map(|deadline| deadline.saturating_duration_since(now)),
+                stop.map(|_| STOP_POLL_INTERVAL),
+                heartbeat.map(|due| due.saturating_duration_since(now)),
+                self.hid
+                    .keepalive_due(self.config.keepalive)
+                    .map(|due| due.saturating_duration_since(now)),
+                watchdog
+                    .as_ref()
+                    .map(|watchdog| watchdog.due.saturating_duration_since(now)),
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+            // A nonblocking fd must always be polled, or an empty read spins;
+            // injections need polling to be noticed at all.
+            let injections = self.injections.as_ref().map(Injections::fd);
+            if timeout.is_some() || self.config.nonblocking || injections.is_some() {
+                let (device_ready, injected) =
+                    wait_input(self.device.as_raw_fd(), injections, timeout)
+                        .context("Failed to poll input device")?;
+                if injected {
+                    self.forward_injections(&mut translator, stats)?;
+                }
+                if !device_ready {
+                    continue;
+                }
+            }
+
+            let events = match self.device.fetch_events() {
+                Ok(events) => events,
+                // Nonblocking and drained: no events right now.
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => continue,
+                // Unplugged: whatever it held must not stay held on the host.
+                Err(error) if error.raw_os_error() == Some(libc::ENODEV) => {
+                    self.hid.write_report(
+                        Report::default().to_bytes(self.config.report_quirk),
+                        stats,
+                    )?;
+                    return Ok(ForwarderExit::DeviceGone);
+                }
+                Err(error) => return Err(error).context("Failed to read input events"),
+            };
+            let now = Instant::now();
+            for event in events {
+                heartbeat = None;
+                stats.events.fetch_add(1, Ordering::Relaxed);
+                if let Some(bytes) = translator.handle(event, now) {
+                    self.hid.write_report(bytes, stats)?;
+                    // Exactly one report, so no burst and no release after it.
+                    if self.config.once {
+                        stats.add_presses(mem::take(&mut translator.presses));
+                        return Ok(ForwarderExit::Quit);
+                    }
+                    while let Some(bytes) = translator.burst() {
+                        self.hid.write_report(bytes, stats)?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        signal_hook::low_level::unregister(self.reset_signal);
+    }
+}
+
+/* Log what kind of device this is, to tell mice from touchpads and
+ * touchscreens. Details go to debug (-v); a device only an absolute report
+ * could do justice to gets a note at info.
+ */
+fn describe_device(device: &Device) {
+    let (major, minor, patch) = device.driver_version();
+    let properties: Vec<String> = device
+        .properties()
+        .iter()
+        .map(|property| format!("INPUT_PROP_{property:?}"))
+        .collect();
+    debug!(
+        "evdev driver {major}.{minor}.{patch}, device properties: {}",
+        if properties.is_empty() {
+            "none".to_string()
+        } else {
+            properties.join(" ")
+        }
+    );
+
+    if device.properties().contains(PropType::DIRECT) {
+        info!(
+            "This looks like a touchscreen (INPUT_PROP_DIRECT); hidex only sends relative \
+             mouse reports, so touches will not be forwarded as positions"
+        );
+    }
+}
+
+/* Whether the device advertises any event the translator turns into
+ * reports, given the filter, remaps and input modes. A power button or an
+ * accelerometer does not.
+ */
+fn translatable(device: &Device, config: &Config) -> bool {
+    let pointer = config.filter != Filter::ButtonsOnly;
+    let buttons = config.filter != Filter::MotionOnly;
+    let relative = |code| {
+        device
+            .supported_relative_axes()
+            .is_some_and(|axes| axes.contains(code))
+    };
+    let stick = config.stick
+        && device.supported_absolute_axes().is_some_and(|axes| {
+            axes.contains(AbsoluteAxisCode::ABS_X) && axes.contains(AbsoluteAxisCode::ABS_Y)
+        });
+    let mapped_key = device.supported_keys().is_some_and(|keys| {
+        keys.iter().any(|key| {
+            config.remap.is_layer_key(key)
+                || config
+                    .remap
+                    .action(key)
+                    .is_some_and(|action| action != Action::Disabled)
+        })
+    });
+
+    (pointer && (relative(RelativeAxisCode::REL_X) || relative(RelativeAxisCode::REL_Y) || stick))
+        || (buttons
+            && (relative(RelativeAxisCode::REL_WHEEL)
+                || relative(RelativeAxisCode::REL_HWHEEL)
+                || (config.dial.is_some() && relative(RelativeAxisCode::REL_DIAL))
+                || mapped_key))
+}
+
+/* Grab the device for exclusive use, retrying transient failures (another
+ * process holding the grab, an interrupted ioctl) `retries` times.
+ */
+pub fn grab(device: &mut Device, source: &Source, retries: u32) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        let error = match device.grab() {
+            Ok(()) => return Ok(()),
+            Err(error) => error,
+        };
+        let errno = error.raw_os_error();
+        let transient = matches!(errno, Some(libc::EBUSY | libc::EINTR | libc::EAGAIN));
+
+        if transient && attempt < retries {
+            attempt += 1;
+            warn!("Failed to grab {source} ({error}), retrying ({attempt}/{retries})");
+            thread::sleep(GRAB_RETRY_DELAY);
+            continue;
+        }
+
+        let hint = match (errno, source) {
+            (Some(libc::EBUSY), _) => {
+                "another program (e.g. a second hidex) has grabbed it; stop it or use --no-grab"
+                    .to_string()
+            }
+            (Some(libc::EACCES | libc::EPERM), Source::Path(path)) => permission_hint(path),
+            (Some(libc::EACCES | libc::EPERM), Source::Fd(_)) => {
+                "the inherited fd lacks permission to grab it".to_string()
+            }
+            _ => "try running as root".to_string(),
+        };
+        return Err(error).with_context(|| format!("Failed to grab {source}: {hint}"));
+    }
+}
+
+/* Copy pre-encoded 5-byte reports from a FIFO straight to the gadget,
+ * bypassing evdev. Bytes are buffered until they make up whole frames, so
+ * a writer may split frames across writes however it likes. When the
+ * writer closes the FIFO it is reopened for the next writer; a frame cut
+ * short by the writer is dropped.
+ */
+pub fn run_fifo(fifo: &Path, config: &Config, stats: &Stats, stop: &AtomicBool) -> Result<()> {
+    let mut hid = Gadget::open(config, Some(stop))?;
+    // Frames are written verbatim, duplicates included.
+    hid.dedup = false;
+
+    let mut source = open_fifo(fifo)?;
+    let mut frames = Frames::default();
+
+    while !stop.load(Ordering::Relaxed) {
+        if !wait_readable(source.as_raw_fd(), Some(STOP_POLL_INTERVAL))
+            .context("Failed to poll input FIFO")?
+        {
+            continue;
+        }
+
+        let read = frames
+            .read_from(&mut source)
+            .context("Failed to read input FIFO")?;
+        if read == 0 {
+            let dropped = frames.discard();
+            if dropped > 0 {
+                warn!(
+                    "Dropping truncated {dropped}-byte frame from {}",
+                    fifo.display()
+                );
+            }
+            debug!("Writer closed {}, reopening", fifo.display());
+            source = open_fifo(fifo)?;
+            continue;
+        }
+
+        while let Some(frame) = frames.next_frame() {
+            stats.events.fetch_add(1, Ordering::Relaxed);
+            hid.write_report(frame, stats)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Bytes read from a report stream, cut into whole frames however they arrive.
+struct Frames {
+    buffer: [u8; FIFO_BUFFER_FRAMES * REPORT_LEN],
+    // Unconsumed bytes are `buffer[start..end]`.
+    start: usize,
+    end: usize,
+}
+
+impl Default for Frames {
+    fn default() -> Self {
+        Self {
+            buffer: [0; FIFO_BUFFER_FRAMES * REPORT_LEN],
+            start: 0,
+            end: 0,
+        }
+    }
+}
+
+impl Frames {
+    // Append what one read of `source` gives; 0 means end of file.
+    fn read_from(&mut self, source: &mut impl Read) -> io::Result<usize> {
+        self.buffer.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+        let read = source.read(&mut self.buffer[self.end..])?;
+        self.end += read;
+        Ok(read)
+    }
+
+    fn next_frame(&mut self) -> Option<[u8; REPORT_LEN]> {
+        if self.end - self.start < REPORT_LEN {
+            return None;
+        }
+        let mut frame = [0u8; REPORT_LEN];
+        frame.copy_from_slice(&self.buffer[self.start..self.start + REPORT_LEN]);
+        self.start += REPORT_LEN;
+        Some(frame)
+    }
+
+    // Drop a partial frame, e.g. one a writer cut short; returns its length.
+    fn discard(&mut self) -> usize {
+        let dropped = self.end - self.start;
+        (self.start, self.end) = (0, 0);
+        dropped
+    }
+}
+
+/* Open the FIFO without waiting for a writer (a blocking open would hang
+ * until one shows up, ignoring the stop flag), then switch to blocking
+ * reads; they only happen once poll says there is something to read.
+ */
+fn open_fifo(path: &Path) -> Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .with_context(|| format!("Failed to open input FIFO {}", path.display()))?;
+
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) } == -1 {
+        return Err(io::Error::last_os_error())
+            .with_context(|| format!("Failed to configure input FIFO {}", path.display()));
+    }
+
+    Ok(file)
+}
+
+// The opened HID gadget plus the timing shown in report trace logs.
+struct Gadget {
+    sink: Box<dyn Write + Send>,
+    // The sink's fd, polled before writes when there is a write timeout.
+    fd: RawFd,
+    // Where reports go, for messages.
+    target: String,
+    // Zero blocks in write() as long as the sink needs.
+    write_timeout: Duration,
+    // How often the host polls the gadget, when that could be found out.
+    poll_interval: Option<Duration>,
+    // Writes at least this slow are warned about; zero doesn't time them.
+    slow_write: Duration,
+    // Warnings about slow writes, and the slowest since the last one.
+    slow_log: RateLimiter,
+    slowest_unlogged: Duration,
+    // Reports a timed-out write left for later, oldest first.
+    backlog: VecDeque<[u8; REPORT_LEN]>,
+    // Whether the last write timed out, so a stall is logged only once.
+    stalled: bool,
+    drop_log: RateLimiter,
+    dedup: bool,
+    last_bytes: Option<[u8; REPORT_LEN]>,
+    // When the last report reached the sink, for the keepalive.
+    last_sent: Instant,
+    // Baseline for trace timestamps, captured at startup.
+    started: Instant,
+    last_write: Option<Instant>,
+}
+
+/* Wait up to `timeout` for the gadget node, which shows up only once the
+ * gadget is set up; at boot that can be after hidex started. Opening it
+ * afterwards reports the error if it is still missing. Setting `stop`
+ * gives up on the wait with an error.
+ */
+fn wait_for_gadget(path: &Path, timeout: Duration, stop: Option<&AtomicBool>) -> Result<()> {
+    if timeout.is_zero() || path.exists() {
+        return Ok(());
+    }
+
+    info!("Waiting up to {timeout:?} for {} to appear", path.display());
+    let deadline = Instant::now() + timeout;
+    let mut attempt = 0;
+    while !path.exists() && Instant::now() < deadline {
+        attempt += 1;
+        debug!("{} not there yet (attempt {attempt})", path.display());
+        if !sleep_unless_stopped(GADGET_WAIT_INTERVAL, stop) {
+            bail!("Stopped while waiting for {} to appear", path.display());
+        }
+    }
+    Ok(())
+}
+
+/* Sleep for `duration`, checking `stop` every STOP_POLL_INTERVAL; false
+ * if it was set before the time was up.
+ */
+fn sleep_unless_stopped(duration: Duration, stop: Option<&AtomicBool>) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        if stop.is_some_and(|stop| stop.load(Ordering::Relaxed)) {
+            return false;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return true;
+        }
+        thread::sleep(left.min(STOP_POLL_INTERVAL));
+    }
+}
+
+impl Gadget {
+    // `stop` cuts short the wait for the gadget node, if there is one.
+    fn open(config: &Config, stop: Option<&AtomicBool>) -> Result<Self> {
+        let mut poll_interval = None;
+        let (sink, fd, target): (Box<dyn Write + Send>, RawFd, String) = match &config.output {
+            Some(path) if path.as_os_str() == "uinput" => {
+                let sink = UinputSink::open()
+                    .context("Failed to create a uinput mouse (is the uinput module loaded?)")?;
+                let fd = sink.as_raw_fd();
+                (Box::new(sink), fd, "uinput".into())
+            }
+            Some(path) if path.as_os_str() == "-" => (
+                Box::new(io::stdout()),
+                io::stdout().as_raw_fd(),
+                "stdout".into(),
+            ),
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(path)
+                    .with_context(|| format!("Failed to open output {}", path.display()))?;
+                let fd = file.as_raw_fd();
+                (Box::new(file), fd, path.display().to_string())
+            }
+            None => {
+                let path = &config.gadget;
+                wait_for_gadget(path, config.wait_gadget, stop)?;
+
+                // Reports are written whole, so a nonblocking gadget can't
+                // leave a partial one behind when the write times out.
+                let flags = if config.write_timeout.is_zero() {
+                    0
+                } else {
+                    libc::O_NONBLOCK
+                };
+                let file = OpenOptions::new()
+                    .write(true)
+                    .custom_flags(flags)
+                    .open(path)
+                    .with_context(|| format!("Failed to open HID gadget at {}", path.display()))?;
+
+                // Writes to an unbound gadget go nowhere, so point that out early.
+                if config.check_udc
+                    && let UdcState::Unbound(gadget) = gadget::probe_udc(path)
+                {
+                    warn!(
+                        "Gadget {} is not bound to a UDC; the host will not see any reports \
+                         (write a controller from /sys/class/udc to its UDC attribute)",
+                        gadget.display()
+                    );
+                }
+
+                poll_interval = gadget::poll_interval(path).map(|(interval, speed)| {
+                    info!(
+                        "The host polls {} every {interval:?} ({speed})",
+                        path.display()
+                    );
+                    interval
+                });
+                if poll_interval.is_none() {
+                    debug!("Polling interval of {} unknown", path.display());
+                }
+
+                // A descriptor that doesn't match makes the host drop our reports.
+                if config.check_descriptor {
+                    match gadget::descriptor_mismatches(path) {
+                        Some(problems) if problems.is_empty() => {
+                            debug!("Gadget report descriptor matches the mouse report")
+                        }
+                        Some(problems) => {
+                            for problem in problems {
+                                warn!("Gadget report descriptor mismatch: {problem}");
+                            }
+                        }
+                        None => debug!("Gadget report descriptor not found, skipping check"),
+                    }
+                }
+                let fd = file.as_raw_fd();
+                (Box::new(file), fd, path.display().to_string())
+            }
+        };
+
+        Ok(Self {
+            sink,
+            fd,
+            target,
+            write_timeout: config.write_timeout,
+            poll_interval,
+            slow_write: config.slow_write,
+            slow_log: RateLimiter::new(DROP_LOG_INTERVAL),
+            slowest_unlogged: Duration::ZERO,
+            backlog: VecDeque::new(),
+            stalled: false,
+            drop_log: RateLimiter::with_threshold(DROP_LOG_INTERVAL, DROP_WARN_THRESHOLD),
+            dedup: config.dedup,
+            last_bytes: None,
+            last_sent: Instant::now(),
+            started: Instant::now(),
+            last_write: None,
+        })
+    }
+
+    fn write_report(&mut self, bytes: [u8; REPORT_LEN], stats: &Stats) -> Result<()> {
+        // A repeat of the previous report without motion tells the host
+        // nothing new (e.g. devices re-sending held buttons). Reports with
+        // motion are always sent: equal deltas in a row are real movement.
+        let motionless = bytes[1..].iter().all(|&byte| byte == 0);
+        if self.dedup && motionless && self.last_bytes == Some(bytes) {
+            trace!("skipping duplicate report {bytes:02x?}");
+            return Ok(());
+        }
+        self.send(bytes, stats)
+    }
+
+    /* When a keepalive is due after `interval` without a write. None while
+     * writes are backed up: the host isn't taking reports anyway.
+     */
+    fn keepalive_due(&self, interval: Duration) -> Option<Instant> {
+        (!interval.is_zero() && self.backlog.is_empty()).then(|| self.last_sent + interval)
+    }
+
+    // Write a keepalive report, which deduplication must not swallow.
+    fn write_keepalive(&mut self, bytes: [u8; REPORT_LEN], stats: &Stats) -> Result<()> {
+        self.send(bytes, stats)
+    }
+
+    fn send(&mut self, bytes: [u8; REPORT_LEN], stats: &Stats) -> Result<()> {
+        self.last_bytes = Some(bytes);
+
+        // Timestamps are for correlating with host-side captures; skip the
+        // clock read unless someone will see them.
+        if log_enabled!(Level::Trace) {
+            let now = Instant::now();
+            let delta = self.last_write.map_or(Duration::ZERO, |last| now - last);
+            self.last_write = Some(now);
+            trace!(
+                "report {bytes:02x?} t={:.6}s dt={:.3}ms",
+                (now - self.started).as_secs_f64(),
+                delta.as_secs_f64() * 1000.0
+            );
+        }
+
+        if self.backlog.len() == BACKLOG_LIMIT {
+            self.backlog.pop_front();
+            self.note_drop(stats);
+        }
+        self.backlog.push_back(bytes);
+        self.drain_backlog(stats)
+    }
+
+    // Write queued reports in order until done or a write times out.
+    fn drain_backlog(&mut self, stats: &Stats) -> Result<()> {
+        while let Some(&bytes) = self.backlog.front() {
+            if !self.write_timeout.is_zero()
+                && !wait_ready(self.fd, libc::POLLOUT, Some(self.write_timeout))
+                    .with_context(|| format!("Failed to poll {}", self.target))?
+            {
+                return self.write_timed_out();
+            }
+
+            // Stdout is line buffered; push every frame out right away.
+            let started = (!self.slow_write.is_zero()).then(Instant::now);
+            match self.sink.write_all(&bytes).and_then(|()| self.sink.flush()) {
+                Ok(()) => {
+                    if let Some(started) = started {
+                        self.note_write_time(started.elapsed(), stats);
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    return self.write_timed_out();
+                }
+                Err(error) => {
+                    return Err(error)
+                        .with_context(|| format!("Failed to write HID report to {}", self.target));
+                }
+            }
+
+            self.backlog.pop_front();
+            self.last_sent = Instant::now();
+            stats.record_report(bytes);
+        }
+
+        if self.stalled {
+            self.stalled = false;
+            info!("Writes to {} go through again", self.target);
+        }
+        Ok(())
+    }
+
+    /* Count a report dropped from the full backlog. Once drops reach
+     * DROP_WARN_THRESHOLD within a DROP_LOG_INTERVAL they are warned about,
+     * summed up at most every interval, so a slow host doesn't also flood
+     * the log and a single hiccup isn't blamed on it.
+     */
+    fn note_drop(&mut self, stats: &Stats) {
+        stats.dropped.fetch_add(1, Ordering::Relaxed);
+        if let Some(count) = self.drop_log.hit(Instant::now()) {
+            warn!(
+                "{} can't keep up: dropped {count} report(s) from a full write backlog since \
+                 the last warning; the host or link is too slow",
+                self.target
+            );
+        }
+    }
+
+    /* Put a timed write into the histogram and warn when it was slow:
+     * the first slow write right away, later ones summed up at most every
+     * DROP_LOG_INTERVAL.
+     */
+    fn note_write_time(&mut self, took: Duration, stats: &Stats) {
+        stats.record_write_time(took);
+        if took < self.slow_write {
+            return;
+        }
+        self.slowest_unlogged = self.slowest_unlogged.max(took);
+        if let Some(count) = self.slow_log.hit(Instant::now()) {
+            let slowest = mem::take(&mut self.slowest_unlogged);
+            warn!(
+                "{count} write(s) to {} took {:?} or longer, the slowest {slowest:?}; \
+                 a slow gadget write delays everything after it",
+                self.target, self.slow_write
+            );
+        }
+    }
+
+    fn write_timed_out(&mut self) -> Result<()> {
+        if !self.stalled {
+            self.stalled = true;
+            warn!(
+                "Write to {} timed out after {:?}, queueing reports",
+                self.target, self.write_timeout
+            );
+        }
+        Ok(())
+    }
+}
+
+/* Wait up to `timeout` for the device or the injection channel to become
+ * readable; returns which of them are.
+ */
+fn wait_input(
+    device: RawFd,
+    injections: Option<RawFd>,
+    timeout: Option<Duration>,
+) -> io::Result<(bool, bool)> {
+    let Some(injections) = injections else {
+        return wait_readable(device, timeout).map(|ready| (ready, false));
+    };
+    let mut pollfds = [device, injections].map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    match unsafe { libc::poll(pollfds.as_mut_ptr(), 2, poll_timeout(timeout)) } {
+        -1 => {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                Ok((false, false))
+            } else {
+                Err(error)
+            }
+        }
+        _ => Ok((pollfds[0].revents != 0, pollfds[1].revents != 0)),
+    }
+}
+
+// Wait up to `timeout` for `fd` to become readable.
+pub fn wait_readable(fd: RawFd, timeout: Option<Duration>) -> io::Result<bool> {
+    wait_ready(fd, libc::POLLIN, timeout)
+}
+
+// Wait up to `timeout` for any of `events` on `fd`.
+fn wait_ready(fd: RawFd, events: libc::c_short, timeout: Option<Duration>) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    };
+    match unsafe { libc::poll(&mut pollfd, 1, poll_timeout(timeout)) } {
+        -1 => {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(error)
+            }
+        }
+        ready => Ok(ready > 0),
+    }
+}
+
+// Poll timeout in ms, rounded up so we never wake just before a deadline
+// and spin; None waits forever.
+fn poll_timeout(timeout: Option<Duration>) -> libc::c_int {
+    timeout.map_or(-1, |timeout| {
+        timeout
+            .as_micros()
+            .div_ceil(1000)
+            .try_into()
+            .unwrap_or(libc::c_int::MAX)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rel(code: RelativeAxisCode, value: i32) -> InputEvent {
+        InputEvent::new(EventType::RELATIVE.0, code.0, value)
+    }
+
+    fn syn() -> InputEvent {
+        InputEvent::new(
+            EventType::SYNCHRONIZATION.0,
+            SynchronizationCode::SYN_REPORT.0,
+            0,
+        )
+    }
+
+    fn key(key: KeyCode, value: i32) -> InputEvent {
+        InputEvent::new(EventType::KEY.0, key.code(), value)
+    }
+
+    #[test]
+    fn motion_accumulates_until_sync() {
+        let mut translator = Translator::new(&Config::default());
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(
+                translator.handle(rel(RelativeAxisCode::REL_X, 10), now),
+                None
+            );
+        }
+        assert_eq!(translator.handle(syn(), now), Some([0, 30, 0, 0, 0]));
+    }
+
+    #[test]
+    fn drain_axis_keeps_what_does_not_fit() {
+        let mut value = 300;
+        assert_eq!(Report::drain_axis(&mut value), 127);
+        assert_eq!(value, 173);
+        let mut value = -300;
+        assert_eq!(Report::drain_axis(&mut value), -128);
+        assert_eq!(value, -172);
+    }
+
+    #[test]
+    fn oversized_delta_is_carried_into_following_reports() {
+        let mut translator = Translator::new(&Config::default());
+        let now = Instant::now();
+        assert_eq!(
+            translator.handle(rel(RelativeAxisCode::REL_X, 300), now),
+            None
+        );
+        let mut sent = vec![translator.handle(syn(), now).unwrap()[1] as i8];
+        while let Some(bytes) = translator.on_timer(now) {
+            sent.push(bytes[1] as i8);
+        }
+        assert_eq!(sent, [127, 127, 46]);
+    }
+
+    #[test]
+    fn reset_forgets_flushed_buttons() {
+        let mut translator = Translator::new(&Config::default());
+        let now = Instant::now();
+        translator.handle(key(KeyCode::BTN_LEFT, 1), now);
+        assert_eq!(translator.handle(syn(), now).unwrap()[0], 0x01);
+        assert_eq!(translator.reset()[0], 0);
+        // A keepalive after the reset must not press the button again.
+        assert_eq!(translator.idle_report()[0], 0);
+    }
+
+    // Reports of one frame with --burst-on-sync: the flush and its burst.
+    fn burst_frame(translator: &mut Translator, x: i32) -> Vec<i8> {
+        let now = Instant::now();
+        translator.handle(rel(RelativeAxisCode::REL_X, x), now);
+        let mut sent = vec![translator.handle(syn(), now).unwrap()[1] as i8];
+        while let Some(bytes) = translator.burst() {
+            sent.push(bytes[1] as i8);
+        }
+        sent
+    }
+
+    #[test]
+    fn burst_sends_a_large_delta_within_the_frame() {
+        let config = Config {
+            burst_on_sync: true,
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        assert_eq!(burst_frame(&mut translator, 400), [127, 127, 127, 19]);
+    }
+
+    #[test]
+    fn burst_is_bounded() {
+        let config = Config {
+            burst_on_sync: true,
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        let sent = burst_frame(&mut translator, i32::MAX / 2);
+        assert_eq!(sent.len(), 1 + 10_000usize.div_ceil(127));
+        assert_eq!(translator.on_timer(Instant::now()), None);
+    }
+
+    #[test]
+    fn spike_is_capped_at_max_carry_and_drains_in_bounded_reports() {
+        let config = Config {
+            max_carry: 1000,
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        let now = Instant::now();
+        translator.handle(rel(RelativeAxisCode::REL_X, 1_000_000), now);
+        let mut sent = vec![translator.handle(syn(), now).unwrap()[1] as i8 as i32];
+        while let Some(bytes) = translator.on_timer(now) {
+            sent.push(bytes[1] as i8 as i32);
+        }
+        // One full report, then the capped carry: 7 x 127 + 111.
+        assert_eq!(sent.len(), 9);
+        assert_eq!(sent.iter().sum::<i32>(), 127 + 1000);
+        assert_eq!(translator.next_deadline(), None);
+    }
+
+    #[test]
+    fn injected_click_while_idle() {
+        let mut translator = Translator::new(&Config::default());
+        assert_eq!(translator.inject([0x01, 0, 0, 0, 0]), [0x01, 0, 0, 0, 0]);
+        assert_eq!(translator.inject([0, 0, 0, 0, 0]), [0, 0, 0, 0, 0]);
+        assert_eq!(translator.on_timer(Instant::now()), None);
+    }
+
+    #[test]
+    fn injected_motion_skips_the_pipeline() {
+        let config = Config {
+            scroll_step: 3.0,
+            hwheel_sign: -1,
+            quantize: (4, 4),
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        let bytes = [0, 1, (-2i8) as u8, 1, 1];
+        assert_eq!(translator.inject(bytes), bytes);
+    }
+
+    #[test]
+    fn stop_cuts_the_gadget_wait_short() {
+        let stop = AtomicBool::new(true);
+        let started = Instant::now();
+        let path = Path::new("/nonexistent/hidg-test");
+        assert!(wait_for_gadget(path, Duration::from_secs(60), Some(&stop)).is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(sleep_unless_stopped(Duration::from_millis(1), None));
+    }
+
+    #[test]
+    fn scroll_step_multiplies_detents() {
+        let config = Config {
+            scroll_step: 3.0,
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        let now = Instant::now();
+        translator.handle(rel(RelativeAxisCode::REL_WHEEL, 1), now);
+        assert_eq!(translator.handle(syn(), now), Some([0, 0, 0, 3, 0]));
+    }
+
+    #[test]
+    fn deferred_applies_changes_once_due() {
+        let now = Instant::now();
+        let mut deferred = Deferred::new(Duration::from_millis(20), false);
+        let mut buttons = 0x01;
+        assert!(deferred.defer(0x01, now));
+        assert!(!deferred.expire(now + Duration::from_millis(10), &mut buttons));
+        assert_eq!(buttons, 0x01);
+        assert!(deferred.expire(now + Duration::from_millis(20), &mut buttons));
+        assert_eq!(buttons, 0);
+        assert!(!Deferred::new(Duration::ZERO, false).defer(0x01, now));
+    }
+
+    #[test]
+    fn debounce_merges_chatter_into_one_hold() {
+        let config = Config {
+            debounce: Duration::from_millis(20),
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        translator.handle(key(KeyCode::BTN_LEFT, 1), at(0));
+        assert_eq!(translator.handle(syn(), at(0)).unwrap()[0], 0x01);
+        // A 10ms bounce: released and pressed again within the window.
+        translator.handle(key(KeyCode::BTN_LEFT, 0), at(5));
+        assert_eq!(translator.handle(syn(), at(5)).unwrap()[0], 0x01);
+        translator.handle(key(KeyCode::BTN_LEFT, 1), at(15));
+        assert_eq!(translator.handle(syn(), at(15)).unwrap()[0], 0x01);
+        assert_eq!(translator.on_timer(at(50)), None);
+        // A real release goes out once the window has passed.
+        translator.handle(key(KeyCode::BTN_LEFT, 0), at(100));
+        assert_eq!(translator.handle(syn(), at(100)).unwrap()[0], 0x01);
+        assert_eq!(translator.on_timer(at(120)).unwrap()[0], 0);
+    }
+
+    #[test]
+    fn wheel_signs_pass_through_and_hwheel_can_flip() {
+        let now = Instant::now();
+        let mut translator = Translator::new(&Config::default());
+        translator.handle(rel(RelativeAxisCode::REL_WHEEL, 1), now);
+        translator.handle(rel(RelativeAxisCode::REL_HWHEEL, 1), now);
+        // Positive evdev values mean up and right, as in HID.
+        assert_eq!(translator.handle(syn(), now), Some([0, 0, 0, 1, 1]));
+
+        let config = Config {
+            hwheel_sign: -1,
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        translator.handle(rel(RelativeAxisCode::REL_WHEEL, 1), now);
+        translator.handle(rel(RelativeAxisCode::REL_HWHEEL, 1), now);
+        assert_eq!(translator.handle(syn(), now), Some([0, 0, 0, 1, 0xff]));
+    }
+
+    #[test]
+    fn wheel_action_sends_its_detents_over_time() {
+        let mut config = Config::default();
+        config.remap.add("BTN_SIDE=wheel:3").unwrap();
+        let mut translator = Translator::new(&config);
+        let start = Instant::now();
+        translator.handle(key(KeyCode::BTN_SIDE, 1), start);
+        let mut total = translator.handle(syn(), start).unwrap()[3] as i8 as i32;
+        let mut now = start;
+        while let Some(deadline) = translator.next_deadline() {
+            now = deadline.max(now);
+            if let Some(bytes) = translator.on_timer(now) {
+                total += bytes[3] as i8 as i32;
+            }
+        }
+        assert_eq!(total, 3);
+    }
+
+    #[test]
+    fn layer_switches_a_button_and_releases_what_it_pressed() {
+        let mut config = Config::default();
+        config.remap.add_layer("BTN_EXTRA:BTN_LEFT=right").unwrap();
+        let mut translator = Translator::new(&config);
+        let now = Instant::now();
+        let mut send = |event| {
+            translator.handle(event, now);
+            translator.handle(syn(), now).unwrap()[0]
+        };
+        // Base layer.
+        assert_eq!(send(key(KeyCode::BTN_LEFT, 1)), 0x01);
+        assert_eq!(send(key(KeyCode::BTN_LEFT, 0)), 0);
+        // Alternate layer while the modifier is held; the modifier itself sends nothing.
+        assert_eq!(send(key(KeyCode::BTN_EXTRA, 1)), 0);
+        assert_eq!(send(key(KeyCode::BTN_LEFT, 1)), 0x02);
+        // Leaving the layer mid-hold still releases the button the press chose.
+        assert_eq!(send(key(KeyCode::BTN_EXTRA, 0)), 0x02);
+        assert_eq!(send(key(KeyCode::BTN_LEFT, 0)), 0);
+    }
+
+    #[test]
+    fn frames_within_the_coalescing_window_make_one_report() {
+        let config = Config {
+            coalesce_window: Duration::from_millis(5),
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        let start = Instant::now();
+        for (ms, x) in [(0, 1), (1, 2), (3, 4)] {
+            let at = start + Duration::from_millis(ms);
+            translator.handle(rel(RelativeAxisCode::REL_X, x), at);
+            assert_eq!(translator.handle(syn(), at), None);
+        }
+        assert_eq!(
+            translator.next_deadline(),
+            Some(start + Duration::from_millis(5))
+        );
+        let at = start + Duration::from_millis(5);
+        assert_eq!(translator.on_timer(at), Some([0, 7, 0, 0, 0]));
+        assert_eq!(translator.on_timer(at), None);
+    }
+
+    #[test]
+    fn tilt_button_scrolls_the_horizontal_wheel_while_held() {
+        let mut config = Config::default();
+        config.remap.add("BTN_SIDE=pan-right").unwrap();
+        let mut translator = Translator::new(&config);
+        let start = Instant::now();
+        translator.handle(key(KeyCode::BTN_SIDE, 1), start);
+        assert_eq!(translator.handle(syn(), start), Some([0, 0, 0, 0, 1]));
+        // Held: another detent each repeat interval.
+        let due = translator.next_deadline().unwrap();
+        assert_eq!(translator.on_timer(due), Some([0, 0, 0, 0, 1]));
+        let at = due + Duration::from_millis(1);
+        translator.handle(key(KeyCode::BTN_SIDE, 0), at);
+        assert_eq!(translator.handle(syn(), at), Some([0, 0, 0, 0, 0]));
+        assert_eq!(translator.next_deadline(), None);
+    }
+
+    #[test]
+    fn report_quirks_rearrange_the_bytes() {
+        let report = Report {
+            buttons: 0x09,
+            motion: Motion {
+                x: 1,
+                y: -1,
+                wheel: 2,
+                hwheel: 3,
+            },
+        };
+        assert_eq!(report.to_bytes(ReportQuirk::None), [0x09, 1, 0xff, 2, 3]);
+        assert_eq!(
+            report.to_bytes(ReportQuirk::ReverseButtonBits),
+            [0x90, 1, 0xff, 2, 3]
+        );
+        assert_eq!(
+            report.to_bytes(ReportQuirk::ReverseBytes),
+            [3, 2, 0xff, 1, 0x09]
+        );
+    }
+
+    #[test]
+    fn dial_drives_the_chosen_wheel_at_its_scale() {
+        let config = Config {
+            dial: Some(Axis::HWheel),
+            dial_scale: 0.5,
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        let now = Instant::now();
+        let mut hwheel = Vec::new();
+        for _ in 0..4 {
+            translator.handle(rel(RelativeAxisCode::REL_DIAL, 1), now);
+            hwheel.push(translator.handle(syn(), now).unwrap()[4]);
+        }
+        assert_eq!(hwheel, [0, 1, 0, 1]);
+
+        let config = Config {
+            dial: None,
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        translator.handle(rel(RelativeAxisCode::REL_DIAL, 1), now);
+        assert_eq!(translator.handle(syn(), now), Some([0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn odd_key_values_press_and_autorepeat_changes_nothing() {
+        let mut translator = Translator::new(&Config::default());
+        let now = Instant::now();
+        translator.handle(key(KeyCode::BTN_LEFT, 5), now);
+        assert_eq!(translator.handle(syn(), now).unwrap()[0], 0x01);
+        translator.handle(key(KeyCode::BTN_LEFT, KEY_REPEAT), now);
+        assert_eq!(translator.handle(syn(), now).unwrap()[0], 0x01);
+        translator.handle(key(KeyCode::BTN_LEFT, 0), now);
+        assert_eq!(translator.handle(syn(), now).unwrap()[0], 0);
+        translator.handle(key(KeyCode::BTN_RIGHT, -1), now);
+        assert_eq!(translator.handle(syn(), now).unwrap()[0], 0x02);
+    }
+
+    #[test]
+    fn min_hold_drops_short_presses_and_delays_long_ones() {
+        let config = Config {
+            min_hold: Duration::from_millis(30),
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        // A 10ms tap never reaches the host.
+        translator.handle(key(KeyCode::BTN_LEFT, 1), at(0));
+        assert_eq!(translator.handle(syn(), at(0)).unwrap()[0], 0);
+        translator.handle(key(KeyCode::BTN_LEFT, 0), at(10));
+        assert_eq!(translator.handle(syn(), at(10)).unwrap()[0], 0);
+        assert_eq!(translator.next_deadline(), None);
+        // A longer hold is pressed once it has lasted the minimum.
+        translator.handle(key(KeyCode::BTN_LEFT, 1), at(100));
+        assert_eq!(translator.handle(syn(), at(100)).unwrap()[0], 0);
+        assert_eq!(translator.next_deadline(), Some(at(130)));
+        assert_eq!(translator.on_timer(at(130)).unwrap()[0], 0x01);
+        translator.handle(key(KeyCode::BTN_LEFT, 0), at(200));
+        assert_eq!(translator.handle(syn(), at(200)).unwrap()[0], 0);
+    }
+
+    #[test]
+    fn resync_restores_held_buttons_and_their_release() {
+        let mut translator = Translator::new(&Config::default());
+        let now = Instant::now();
+        translator.handle(key(KeyCode::BTN_MIDDLE, 1), now);
+        translator.handle(syn(), now);
+
+        let mut held = evdev::AttributeSet::<KeyCode>::new();
+        held.insert(KeyCode::BTN_LEFT);
+        held.insert(KeyCode::KEY_A);
+        // The stale middle press is released first, then left is restored.
+        assert_eq!(
+            translator.resync(&held),
+            [[0, 0, 0, 0, 0], [0x01, 0, 0, 0, 0]]
+        );
+        // A restored press is released like any other.
+        translator.handle(key(KeyCode::BTN_LEFT, 0), now);
+        assert_eq!(translator.handle(syn(), now).unwrap()[0], 0);
+    }
+
+    // A writer that hands over its bytes one at a time.
+    struct Dribble<'a>(&'a [u8]);
+
+    impl Read for Dribble<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((&byte, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = byte;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn frames_are_reassembled_from_dribbled_bytes() {
+        let bytes = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let mut source = Dribble(&bytes);
+        let mut frames = Frames::default();
+        let mut sent = Vec::new();
+        while frames.read_from(&mut source).unwrap() > 0 {
+            sent.extend(std::iter::from_fn(|| frames.next_frame()));
+        }
+        assert_eq!(sent, [[1, 2, 3, 4, 5], [6, 7, 8, 9, 10]]);
+        // The two bytes left at end of file are not a report.
+        assert_eq!(frames.discard(), 2);
+        assert_eq!(frames.next_frame(), None);
+    }
+
+    #[test]
+    fn held_scroll_button_routes_both_axes_into_the_wheels() {
+        let config = Config {
+            middle_scroll: true,
+            middle_scroll_scale: (0.5, 1.0),
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        let now = Instant::now();
+        translator.handle(key(KeyCode::BTN_MIDDLE, 1), now);
+        assert_eq!(translator.handle(syn(), now), Some([0, 0, 0, 0, 0]));
+        // Right and up: pans right by half, scrolls up one to one.
+        translator.handle(rel(RelativeAxisCode::REL_X, 4), now);
+        translator.handle(rel(RelativeAxisCode::REL_Y, -2), now);
+        assert_eq!(translator.handle(syn(), now), Some([0, 0, 0, 2, 2]));
+        // Releasing after scrolling is not a click.
+        translator.handle(key(KeyCode::BTN_MIDDLE, 0), now);
+        assert_eq!(translator.handle(syn(), now), Some([0, 0, 0, 0, 0]));
+        translator.handle(rel(RelativeAxisCode::REL_X, 4), now);
+        assert_eq!(translator.handle(syn(), now), Some([0, 4, 0, 0, 0]));
+    }
+
+    #[test]
+    fn disabled_hwheel_stays_zero() {
+        let config = Config {
+            forward_hwheel: false,
+            ..Config::default()
+        };
+        let mut translator = Translator::new(&config);
+        let now = Instant::now();
+        translator.handle(rel(RelativeAxisCode::REL_WHEEL, 1), now);
+        translator.handle(rel(RelativeAxisCode::REL_HWHEEL, 3), now);
+        assert_eq!(translator.handle(syn(), now), Some([0, 0, 0, 1, 0]));
+        // Other axes are unaffected.
+        translator.handle(rel(RelativeAxisCode::REL_X, 2), now);
+        translator.handle(rel(RelativeAxisCode::REL_HWHEEL, -1), now);
+        assert_eq!(translator.handle(syn(), now), Some([0, 2, 0, 0, 0]));
     }
 }
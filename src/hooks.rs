@@ -0,0 +1,56 @@
+use std::{
+    io,
+    os::fd::AsFd,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+// How long a hook may run before it is killed.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+// How often to check whether a hook has finished.
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/* Run a user hook with `sh -c`, waiting for it up to `HOOK_TIMEOUT`.
+ * `env` is added to hidex's own environment. The hook's output goes to
+ * stderr, since stdout may carry reports. Failures are logged, never
+ * returned: a broken hook must not stop forwarding.
+ */
+pub fn run(name: &str, command: &str, env: &[(&str, &str)]) {
+    debug!("Running {name} hook: {command}");
+    if let Err(error) = spawn_and_wait(name, command, env) {
+        warn!("Failed to run {name} hook: {error}");
+    }
+}
+
+fn spawn_and_wait(name: &str, command: &str, env: &[(&str, &str)]) -> io::Result<()> {
+    let stderr = io::stderr().as_fd().try_clone_to_owned()?;
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .envs(env.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(stderr))
+        .spawn()?;
+
+    let deadline = Instant::now() + HOOK_TIMEOUT;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            if status.success() {
+                debug!("{name} hook finished");
+            } else {
+                warn!("{name} hook failed ({status})");
+            }
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            warn!("{name} hook still running after {HOOK_TIMEOUT:?}, killing it");
+            child.kill()?;
+            child.wait()?;
+            return Ok(());
+        }
+        thread::sleep(HOOK_POLL_INTERVAL);
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    io,
+    os::{
+        fd::{AsRawFd, RawFd},
+        unix::net::UnixDatagram,
+    },
+    sync::Arc,
+};
+
+use crate::report::REPORT_LEN;
+
+/* A channel for reports that don't come from the input device, e.g.
+ * automation on the introspection socket. Each report travels as one
+ * datagram, so they arrive whole and in order, and the receiving end is an
+ * fd the forwarder can poll next to the device.
+ */
+pub fn channel() -> io::Result<(Injector, Injections)> {
+    let (sender, receiver) = UnixDatagram::pair()?;
+    receiver.set_nonblocking(true)?;
+    Ok((
+        Injector { socket: sender },
+        Injections {
+            socket: Arc::new(receiver),
+        },
+    ))
+}
+
+// Sending end, shared by whoever injects.
+pub struct Injector {
+    socket: UnixDatagram,
+}
+
+impl Injector {
+    pub fn send(&self, report: [u8; REPORT_LEN]) -> io::Result<()> {
+        self.socket.send(&report).map(|_| ())
+    }
+}
+
+// Receiving end, handed from one forwarder to the next.
+#[derive(Clone)]
+pub struct Injections {
+    socket: Arc<UnixDatagram>,
+}
+
+impl Injections {
+    pub fn fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+
+    // Next injected report, or None if there is none right now.
+    pub fn try_recv(&self) -> io::Result<Option<[u8; REPORT_LEN]>> {
+        let mut report = [0; REPORT_LEN];
+        match self.socket.recv(&mut report) {
+            Ok(REPORT_LEN) => Ok(Some(report)),
+            Ok(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated injected report",
+            )),
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    env,
+    ffi::CStr,
+    fmt, fs, io,
+    os::{
+        fd::{FromRawFd, OwnedFd, RawFd},
+        unix::fs::MetadataExt,
+    },
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::{Context, Result, bail};
+use evdev::{Device, KeyCode, RelativeAxisCode};
+
+const INPUT_DIR: &str = "/dev/input";
+
+// First fd passed by systemd socket activation (SD_LISTEN_FDS_START).
+const LISTEN_FDS_START: RawFd = 3;
+
+// Where the input device comes from.
+pub enum Source {
+    Path(PathBuf),
+    // Already open descriptor, e.g. handed over by systemd.
+    Fd(RawFd),
+}
+
+impl Source {
+    pub fn open(&self) -> Result<Device> {
+        match self {
+            Source::Path(path) => match Device::open(path) {
+                Err(error) if error.kind() == io::ErrorKind::PermissionDenied => Err(error)
+                    .with_context(|| {
+                        format!(
+                            "Permission denied opening input device {}: {}",
+                            path.display(),
+                            permission_hint(path)
+                        )
+                    }),
+                result => result
+                    .with_context(|| format!("Failed to open input device {}", path.display())),
+            },
+            Source::Fd(fd) => device_from_fd(*fd),
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Path(path) => write!(f, "{}", path.display()),
+            Source::Fd(fd) => write!(f, "fd {fd}"),
+        }
+    }
+}
+
+/* The fd systemd passed us via socket activation (e.g. a socket unit with
+ * ListenSpecial=/dev/input/eventN), if any. Only the first one is used.
+ */
+pub fn listen_fd() -> Option<RawFd> {
+    let pid: u32 = env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: u32 = env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    (pid == process::id() && fds >= 1).then_some(LISTEN_FDS_START)
+}
+
+/* What to do about a permission error on an input device node: which
+ * group owns it and whether the current user is already in that group.
+ */
+pub fn permission_hint(path: &Path) -> String {
+    let Ok(meta) = fs::metadata(path) else {
+        return "try running as root".to_string();
+    };
+    let gid = meta.gid();
+    let group = group_name(gid).unwrap_or_else(|| gid.to_string());
+
+    if in_group(gid) {
+        format!(
+            "it is owned by group '{group}' with mode {:o} and you are already in that group; \
+             check the mode or run as root",
+            meta.mode() & 0o777
+        )
+    } else {
+        format!(
+            "it is owned by group '{group}', which you are not in; run as root or add \
+             yourself with `usermod -aG {group} $USER` and log in again"
+        )
+    }
+}
+
+fn group_name(gid: libc::gid_t) -> Option<String> {
+    // Safety: getgrgid returns null or a pointer to a static entry that
+    // stays valid until the next getgr* call; it is copied out right away.
+    let entry = unsafe { libc::getgrgid(gid) };
+    if entry.is_null() {
+        return None;
+    }
+    let name = unsafe { CStr::from_ptr((*entry).gr_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+// Whether `gid` is the effective group or one of the supplementary groups.
+fn in_group(gid: libc::gid_t) -> bool {
+    if unsafe { libc::getegid() } == gid {
+        return true;
+    }
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups = vec![0; count.max(0) as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    groups.truncate(count.max(0) as usize);
+    groups.contains(&gid)
+}
+
+// Take ownership of an inherited fd, making sure it really is an evdev device.
+fn device_from_fd(fd: RawFd) -> Result<Device> {
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        bail!("fd {fd} is not open");
+    }
+
+    // Safety: the fd is open and nothing else in this process owns it; it
+    // was inherited for exactly this purpose.
+    let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+    Device::from_fd(owned).with_context(|| format!("fd {fd} is not an evdev input device"))
+}
+
+// Single input device found under /dev/input.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DeviceEntry {
+    pub path: PathBuf,
+    pub name: String,
+}
+
+// Scan /dev/input/event* and collect their names.
+#[cfg(feature = "tui")]
+pub fn scan_devices() -> io::Result<Vec<DeviceEntry>> {
+    Ok(open_event_devices()?
+        .into_iter()
+        .map(|(path, dev)| DeviceEntry {
+            path,
+            name: dev.name().unwrap_or("Unknown device").to_string(),
+        })
+        .collect())
+}
+
+// First device (in path order) that looks like a mouse.
+pub fn first_pointer_device() -> io::Result<Option<DeviceEntry>> {
+    Ok(open_event_devices()?
+        .into_iter()
+        .find(|(_, dev)| is_pointer(dev))
+        .map(|(path, dev)| DeviceEntry {
+            path,
+            name: dev.name().unwrap_or("Unknown device").to_string(),
+        }))
+}
+
+// Relative X/Y motion plus a left button.
+fn is_pointer(dev: &Device) -> bool {
+    let has_motion = dev.supported_relative_axes().is_some_and(|axes| {
+        axes.contains(RelativeAxisCode::REL_X) && axes.contains(RelativeAxisCode::REL_Y)
+    });
+    let has_button = dev
+        .supported_keys()
+        .is_some_and(|keys| keys.contains(KeyCode::BTN_LEFT));
+    has_motion && has_button
+}
+
+// Open every readable /dev/input/event* node, sorted by path.
+fn open_event_devices() -> io::Result<Vec<(PathBuf, Device)>> {
+    let mut devices = Vec::new();
+
+    for entry in fs::read_dir(INPUT_DIR)? {
+        let entry = entry?;
+        let path = entry.path();
+
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if !file_name.starts_with("event") {
+            continue;
+        }
+
+        if let Ok(dev) = Device::open(&path) {
+            devices.push((path, dev));
+        }
+    }
+
+    devices.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(devices)
+}
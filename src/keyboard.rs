@@ -0,0 +1,212 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    os::fd::AsRawFd,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use anyhow::{Context, Result};
+use evdev::{Device, EventSummary, KeyCode};
+use log::{debug, error, info, trace, warn};
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use crate::{
+    hid::{self, KEY_REPEAT, STOP_POLL_INTERVAL},
+    input::Source,
+    keymap,
+};
+
+// Default gadget node of the keyboard function, next to the mouse's hidg1.
+pub const KEYBOARD_GADGET_PATH: &str = "/dev/hidg0";
+
+// Length of a boot keyboard report: modifiers, reserved, six key slots.
+const KEYBOARD_REPORT_LEN: usize = 8;
+
+// Keys a boot report can hold at once.
+const KEY_SLOTS: usize = 6;
+
+// Usage filling every key slot when more keys are held than fit.
+const ERROR_ROLL_OVER: u8 = 0x01;
+
+// Keyboard state as the host should see it.
+#[derive(Default, PartialEq)]
+struct KeyboardReport {
+    modifiers: u8,
+    // Usages of the held keys, in the order they were pressed.
+    keys: Vec<u8>,
+}
+
+impl KeyboardReport {
+    // Apply a press (true) or release (false); false if `key` has no usage.
+    fn set(&mut self, key: KeyCode, pressed: bool) -> bool {
+        if let Some(bit) = keymap::modifier(key) {
+            if pressed {
+                self.modifiers |= bit;
+            } else {
+                self.modifiers &= !bit;
+            }
+            return true;
+        }
+        let Some(usage) = keymap::usage(key) else {
+            return false;
+        };
+        self.keys.retain(|&held| held != usage);
+        if pressed {
+            self.keys.push(usage);
+        }
+        true
+    }
+
+    /* Bytes of the boot report. With more keys held than there are slots
+     * the host gets the error roll-over state, which keeps what it already
+     * has pressed, until enough are released again.
+     */
+    fn to_bytes(&self) -> [u8; KEYBOARD_REPORT_LEN] {
+        let mut bytes = [0u8; KEYBOARD_REPORT_LEN];
+        bytes[0] = self.modifiers;
+        if self.keys.len() > KEY_SLOTS {
+            bytes[2..].fill(ERROR_ROLL_OVER);
+        } else {
+            bytes[2..2 + self.keys.len()].copy_from_slice(&self.keys);
+        }
+        bytes
+    }
+}
+
+/* Forward a keyboard's keys to the keyboard gadget at `hid` as boot
+ * reports until Ctrl+C / SIGTERM or the device goes away. Keys without a
+ * usage are ignored and autorepeat is left to the host. Everything is
+ * released on the host before returning.
+ */
+pub fn run_keyboard_forwarder(input: &Path, hid: &Path) -> Result<()> {
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&stop))
+            .context("Failed to install signal handler")?;
+    }
+
+    let source = Source::Path(input.to_path_buf());
+    let mut device = source.open()?;
+    hid::grab(&mut device, &source, 0)?;
+    let gadget = hid;
+    let mut hid = OpenOptions::new()
+        .write(true)
+        .open(gadget)
+        .with_context(|| format!("Failed to open HID gadget at {}", gadget.display()))?;
+    info!(
+        "Forwarding {source} ({}) to {} as an {KEYBOARD_REPORT_LEN}-byte keyboard report",
+        device.name().unwrap_or("Unknown device"),
+        gadget.display()
+    );
+
+    let mut report = KeyboardReport::default();
+    let result = forward_keys(&mut device, &source, &mut hid, &mut report, &stop);
+
+    // Nothing may stay held on the host once we stop forwarding; failing
+    // that must not hide why forwarding stopped.
+    if report != KeyboardReport::default()
+        && let Err(error) = write(&mut hid, &KeyboardReport::default().to_bytes())
+    {
+        if result.is_err() {
+            error!("Failed to release all keys on the host: {error:#}");
+        } else {
+            return Err(error);
+        }
+    }
+    result
+}
+
+// Translate key events into reports until stopped or the device is gone.
+fn forward_keys(
+    device: &mut Device,
+    source: &Source,
+    hid: &mut File,
+    report: &mut KeyboardReport,
+    stop: &AtomicBool,
+) -> Result<()> {
+    while !stop.load(Ordering::Relaxed) {
+        if !hid::wait_readable(device.as_raw_fd(), Some(STOP_POLL_INTERVAL))
+            .context("Failed to poll input device")?
+        {
+            continue;
+        }
+        let events = match device.fetch_events() {
+            Ok(events) => events,
+            Err(error) if error.raw_os_error() == Some(libc::ENODEV) => {
+                warn!("{source} went away");
+                return Ok(());
+            }
+            Err(error) => return Err(error).context("Failed to read input events"),
+        };
+        let previous = report.to_bytes();
+        for event in events {
+            if let EventSummary::Key(_, key, value) = event.destructure()
+                && value != KEY_REPEAT
+                && !report.set(key, value != 0)
+            {
+                debug!("No keyboard usage for {key:?}, ignoring it");
+            }
+        }
+        let bytes = report.to_bytes();
+        if bytes != previous {
+            trace!("Keyboard report {bytes:02x?}");
+            write(hid, &bytes)?;
+        }
+    }
+    Ok(())
+}
+
+fn write(hid: &mut File, bytes: &[u8]) -> Result<()> {
+    hid.write_all(bytes)
+        .and_then(|()| hid.flush())
+        .context("Failed to write keyboard report")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn press_and_release_fill_and_clear_slots() {
+        let mut report = KeyboardReport::default();
+        assert!(report.set(KeyCode::KEY_LEFTSHIFT, true));
+        assert!(report.set(KeyCode::KEY_A, true));
+        assert!(report.set(KeyCode::KEY_B, true));
+        assert_eq!(report.to_bytes(), [0x02, 0, 0x04, 0x05, 0, 0, 0, 0]);
+        assert!(report.set(KeyCode::KEY_A, false));
+        assert!(report.set(KeyCode::KEY_LEFTSHIFT, false));
+        assert_eq!(report.to_bytes(), [0, 0, 0x05, 0, 0, 0, 0, 0]);
+        assert!(!report.set(KeyCode::BTN_LEFT, true));
+    }
+
+    #[test]
+    fn seventh_key_rolls_over() {
+        let mut report = KeyboardReport::default();
+        report.set(KeyCode::KEY_LEFTCTRL, true);
+        for key in [
+            KeyCode::KEY_A,
+            KeyCode::KEY_B,
+            KeyCode::KEY_C,
+            KeyCode::KEY_D,
+            KeyCode::KEY_E,
+            KeyCode::KEY_F,
+        ] {
+            report.set(key, true);
+        }
+        assert_eq!(
+            report.to_bytes(),
+            [0x01, 0, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09]
+        );
+        report.set(KeyCode::KEY_G, true);
+        assert_eq!(report.to_bytes(), [0x01, 0, 1, 1, 1, 1, 1, 1]);
+        report.set(KeyCode::KEY_A, false);
+        assert_eq!(
+            report.to_bytes(),
+            [0x01, 0, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a]
+        );
+    }
+}
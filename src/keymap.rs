@@ -0,0 +1,178 @@
+/* Translation of evdev key codes to USB HID keyboard usages (usage page
+ * 0x07) for the 8-byte boot keyboard report. Modifiers aren't usages in
+ * that report but bits of its first byte, so they have their own table.
+ */
+
+use evdev::KeyCode;
+
+// Modifier bits in the first byte of a boot keyboard report.
+pub const MOD_LEFT_CTRL: u8 = 0x01;
+pub const MOD_LEFT_SHIFT: u8 = 0x02;
+pub const MOD_LEFT_ALT: u8 = 0x04;
+pub const MOD_LEFT_META: u8 = 0x08;
+pub const MOD_RIGHT_CTRL: u8 = 0x10;
+pub const MOD_RIGHT_SHIFT: u8 = 0x20;
+pub const MOD_RIGHT_ALT: u8 = 0x40;
+pub const MOD_RIGHT_META: u8 = 0x80;
+
+// Modifier bit of `key`, if it is one of the eight modifier keys.
+pub fn modifier(key: KeyCode) -> Option<u8> {
+    let bit = match key {
+        KeyCode::KEY_LEFTCTRL => MOD_LEFT_CTRL,
+        KeyCode::KEY_LEFTSHIFT => MOD_LEFT_SHIFT,
+        KeyCode::KEY_LEFTALT => MOD_LEFT_ALT,
+        KeyCode::KEY_LEFTMETA => MOD_LEFT_META,
+        KeyCode::KEY_RIGHTCTRL => MOD_RIGHT_CTRL,
+        KeyCode::KEY_RIGHTSHIFT => MOD_RIGHT_SHIFT,
+        KeyCode::KEY_RIGHTALT => MOD_RIGHT_ALT,
+        KeyCode::KEY_RIGHTMETA => MOD_RIGHT_META,
+        _ => return None,
+    };
+    Some(bit)
+}
+
+// Keyboard usage of `key`, e.g. 0x04 for KEY_A; None for keys without one.
+pub fn usage(key: KeyCode) -> Option<u8> {
+    let usage = match key {
+        KeyCode::KEY_A => 0x04,
+        KeyCode::KEY_B => 0x05,
+        KeyCode::KEY_C => 0x06,
+        KeyCode::KEY_D => 0x07,
+        KeyCode::KEY_E => 0x08,
+        KeyCode::KEY_F => 0x09,
+        KeyCode::KEY_G => 0x0a,
+        KeyCode::KEY_H => 0x0b,
+        KeyCode::KEY_I => 0x0c,
+        KeyCode::KEY_J => 0x0d,
+        KeyCode::KEY_K => 0x0e,
+        KeyCode::KEY_L => 0x0f,
+        KeyCode::KEY_M => 0x10,
+        KeyCode::KEY_N => 0x11,
+        KeyCode::KEY_O => 0x12,
+        KeyCode::KEY_P => 0x13,
+        KeyCode::KEY_Q => 0x14,
+        KeyCode::KEY_R => 0x15,
+        KeyCode::KEY_S => 0x16,
+        KeyCode::KEY_T => 0x17,
+        KeyCode::KEY_U => 0x18,
+        KeyCode::KEY_V => 0x19,
+        KeyCode::KEY_W => 0x1a,
+        KeyCode::KEY_X => 0x1b,
+        KeyCode::KEY_Y => 0x1c,
+        KeyCode::KEY_Z => 0x1d,
+        KeyCode::KEY_1 => 0x1e,
+        KeyCode::KEY_2 => 0x1f,
+        KeyCode::KEY_3 => 0x20,
+        KeyCode::KEY_4 => 0x21,
+        KeyCode::KEY_5 => 0x22,
+        KeyCode::KEY_6 => 0x23,
+        KeyCode::KEY_7 => 0x24,
+        KeyCode::KEY_8 => 0x25,
+        KeyCode::KEY_9 => 0x26,
+        KeyCode::KEY_0 => 0x27,
+        KeyCode::KEY_ENTER => 0x28,
+        KeyCode::KEY_ESC => 0x29,
+        KeyCode::KEY_BACKSPACE => 0x2a,
+        KeyCode::KEY_TAB => 0x2b,
+        KeyCode::KEY_SPACE => 0x2c,
+        KeyCode::KEY_MINUS => 0x2d,
+        KeyCode::KEY_EQUAL => 0x2e,
+        KeyCode::KEY_LEFTBRACE => 0x2f,
+        KeyCode::KEY_RIGHTBRACE => 0x30,
+        KeyCode::KEY_BACKSLASH => 0x31,
+        KeyCode::KEY_SEMICOLON => 0x33,
+        KeyCode::KEY_APOSTROPHE => 0x34,
+        KeyCode::KEY_GRAVE => 0x35,
+        KeyCode::KEY_COMMA => 0x36,
+        KeyCode::KEY_DOT => 0x37,
+        KeyCode::KEY_SLASH => 0x38,
+        KeyCode::KEY_CAPSLOCK => 0x39,
+        KeyCode::KEY_F1 => 0x3a,
+        KeyCode::KEY_F2 => 0x3b,
+        KeyCode::KEY_F3 => 0x3c,
+        KeyCode::KEY_F4 => 0x3d,
+        KeyCode::KEY_F5 => 0x3e,
+        KeyCode::KEY_F6 => 0x3f,
+        KeyCode::KEY_F7 => 0x40,
+        KeyCode::KEY_F8 => 0x41,
+        KeyCode::KEY_F9 => 0x42,
+        KeyCode::KEY_F10 => 0x43,
+        KeyCode::KEY_F11 => 0x44,
+        KeyCode::KEY_F12 => 0x45,
+        KeyCode::KEY_SYSRQ => 0x46,
+        KeyCode::KEY_SCROLLLOCK => 0x47,
+        KeyCode::KEY_PAUSE => 0x48,
+        KeyCode::KEY_INSERT => 0x49,
+        KeyCode::KEY_HOME => 0x4a,
+        KeyCode::KEY_PAGEUP => 0x4b,
+        KeyCode::KEY_DELETE => 0x4c,
+        KeyCode::KEY_END => 0x4d,
+        KeyCode::KEY_PAGEDOWN => 0x4e,
+        KeyCode::KEY_RIGHT => 0x4f,
+        KeyCode::KEY_LEFT => 0x50,
+        KeyCode::KEY_DOWN => 0x51,
+        KeyCode::KEY_UP => 0x52,
+        KeyCode::KEY_NUMLOCK => 0x53,
+        KeyCode::KEY_KPSLASH => 0x54,
+        KeyCode::KEY_KPASTERISK => 0x55,
+        KeyCode::KEY_KPMINUS => 0x56,
+        KeyCode::KEY_KPPLUS => 0x57,
+        KeyCode::KEY_KPENTER => 0x58,
+        KeyCode::KEY_KP1 => 0x59,
+        KeyCode::KEY_KP2 => 0x5a,
+        KeyCode::KEY_KP3 => 0x5b,
+        KeyCode::KEY_KP4 => 0x5c,
+        KeyCode::KEY_KP5 => 0x5d,
+        KeyCode::KEY_KP6 => 0x5e,
+        KeyCode::KEY_KP7 => 0x5f,
+        KeyCode::KEY_KP8 => 0x60,
+        KeyCode::KEY_KP9 => 0x61,
+        KeyCode::KEY_KP0 => 0x62,
+        KeyCode::KEY_KPDOT => 0x63,
+        KeyCode::KEY_102ND => 0x64,
+        KeyCode::KEY_COMPOSE => 0x65,
+        KeyCode::KEY_KPEQUAL => 0x67,
+        KeyCode::KEY_F13 => 0x68,
+        KeyCode::KEY_F14 => 0x69,
+        KeyCode::KEY_F15 => 0x6a,
+        KeyCode::KEY_F16 => 0x6b,
+        KeyCode::KEY_F17 => 0x6c,
+        KeyCode::KEY_F18 => 0x6d,
+        KeyCode::KEY_F19 => 0x6e,
+        KeyCode::KEY_F20 => 0x6f,
+        KeyCode::KEY_F21 => 0x70,
+        KeyCode::KEY_F22 => 0x71,
+        KeyCode::KEY_F23 => 0x72,
+        KeyCode::KEY_F24 => 0x73,
+        _ => return None,
+    };
+    Some(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_map_to_their_usages() {
+        assert_eq!(usage(KeyCode::KEY_A), Some(0x04));
+        assert_eq!(usage(KeyCode::KEY_Z), Some(0x1d));
+        assert_eq!(usage(KeyCode::KEY_1), Some(0x1e));
+        assert_eq!(usage(KeyCode::KEY_0), Some(0x27));
+        assert_eq!(usage(KeyCode::KEY_F1), Some(0x3a));
+        assert_eq!(usage(KeyCode::KEY_F12), Some(0x45));
+        assert_eq!(usage(KeyCode::KEY_KPEQUAL), Some(0x67));
+        assert_eq!(usage(KeyCode::BTN_LEFT), None);
+    }
+
+    #[test]
+    fn modifiers_map_to_their_bits() {
+        assert_eq!(modifier(KeyCode::KEY_LEFTCTRL), Some(0x01));
+        assert_eq!(modifier(KeyCode::KEY_LEFTSHIFT), Some(0x02));
+        assert_eq!(modifier(KeyCode::KEY_RIGHTALT), Some(0x40));
+        assert_eq!(modifier(KeyCode::KEY_RIGHTMETA), Some(0x80));
+        assert_eq!(modifier(KeyCode::KEY_A), None);
+        // Modifiers are bits, never key slots.
+        assert_eq!(usage(KeyCode::KEY_LEFTSHIFT), None);
+    }
+}
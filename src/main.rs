@@ -1,7 +1,41 @@
+mod caps;
+mod cli;
+mod gadget;
 mod hid;
+mod hooks;
+mod inject;
+mod input;
+mod keyboard;
+mod keymap;
+mod motion;
+mod ratelimit;
+mod remap;
+mod report;
+mod socket;
+mod stats;
+mod stick;
+#[cfg(feature = "tui")]
 mod tui;
+mod uinput;
 
-use std::{path::PathBuf, process};
+use std::{
+    io::{self, IsTerminal},
+    path::PathBuf,
+    process,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+};
+
+use anyhow::Context;
+use env_logger::Env;
+use hid::ForwarderExit;
+use input::Source;
+use log::{LevelFilter, info};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use stats::Stats;
 
 fn main() {
     if let Err(error) = run() {
@@ -11,19 +45,264 @@ fn main() {
 }
 
 fn run() -> anyhow::Result<()> {
-    // 1. Run the TUI device picker
-    let selected: Option<PathBuf> = tui::pick_device()?;
+    let mut args = match cli::parse()? {
+        cli::Command::Forward(args) => args,
+        cli::Command::Caps { path, json } => return caps::print(&path, json),
+        cli::Command::Gadgets => return list_gadgets(),
+        cli::Command::Keyboard {
+            input,
+            gadget,
+            log_level,
+        } => {
+            init_logging(log_level);
+            println!(
+                "Forwarding keyboard {}. Press Ctrl+C to stop.",
+                input.display()
+            );
+            return keyboard::run_keyboard_forwarder(&input, &gadget);
+        }
+    };
+    init_logging(args.log_level);
+
+    // With reports on stdout, the TUI and status messages must stay off it.
+    let stdout_taken = args.stdout_taken();
 
-    let Some(input_path) = selected else {
-        println!("No device selected. Exiting.");
-        return Ok(());
+    // Pre-encoded reports from a FIFO skip device selection entirely
+    if let Some(fifo) = &args.input_fifo {
+        let stop = Arc::new(AtomicBool::new(false));
+        register_stop_signals(&stop)?;
+        notice(
+            stdout_taken,
+            &format!(
+                "Forwarding reports from {}. Press Ctrl+C to stop.",
+                fifo.display()
+            ),
+        );
+        return hid::run_fifo(fifo, &args.config, &Stats::default(), &stop);
+    }
+
+    // 1. Pick the input device: an inherited fd, explicit --input, the TUI,
+    //    or a fallback when there is no terminal to run the TUI in
+    let interactive = io::stdin().is_terminal() && !stdout_taken;
+    let source = if let Some(fd) = args.fd.or_else(input::listen_fd) {
+        Source::Fd(fd)
+    } else if let Some(path) = args.input.take() {
+        Source::Path(path)
+    } else if interactive {
+        let Some(path) = pick_device(&mut args.config.gadget)? else {
+            notice(stdout_taken, "No device selected. Exiting.");
+            return Ok(());
+        };
+        Source::Path(path)
+    } else if !args.auto_select {
+        anyhow::bail!(
+            "No input device given and no terminal for the device picker; select one with \
+             --input <PATH>, --fd <N>, systemd socket activation (LISTEN_FDS) or \
+             --input-fifo <PATH>"
+        );
+    } else {
+        let device = input::first_pointer_device()
+            .context("Failed to scan input devices")?
+            .context(
+                "No terminal for the device picker and no pointer device found; use --input",
+            )?;
+        info!(
+            "No terminal, auto-selected {} ({})",
+            device.path.display(),
+            device.name
+        );
+        Source::Path(device.path)
     };
 
-    println!("Selected input device: {}", input_path.display());
-    println!("Starting HID forwarding. Press Ctrl+C to stop.");
+    notice(stdout_taken, &format!("Selected input device: {source}"));
+
+    // Ctrl+C / SIGTERM stop forwarding cleanly, ungrabbing the device,
+    // and cut short the waits while opening it.
+    let stop = Arc::new(AtomicBool::new(false));
+    register_stop_signals(&stop)?;
 
     // 2. Start forwarding events from evdev to the HID gadget
-    hid::run_forwarder(&input_path)?;
+    let mut forwarder = hid::Forwarder::open(&source, &args.config, &stop)?;
+    let (injector, injections) = if args.inject {
+        let (injector, injections) =
+            inject::channel().context("Failed to set up report injection")?;
+        (Some(injector), Some(injections))
+    } else {
+        (None, None)
+    };
+    if let Some(injections) = &injections {
+        forwarder.set_injections(injections.clone());
+    }
+    let server = args
+        .socket
+        .as_deref()
+        .map(|path| socket::Server::bind(path, injector))
+        .transpose()?;
+    let stats = Stats::default();
+
+    thread::scope(|scope| {
+        if let Some(server) = &server {
+            scope.spawn(|| server.serve(&stats, &stop));
+        }
+
+        let result = forward(
+            forwarder,
+            source,
+            injections.as_ref(),
+            &args,
+            interactive,
+            &stats,
+            &stop,
+        );
+        stop.store(true, Ordering::Relaxed);
+        result
+    })?;
+
+    let presses: Vec<String> = stats
+        .presses()
+        .into_iter()
+        .filter(|&(_, count)| count != 0)
+        .map(|(name, count)| format!("{name} {count}"))
+        .collect();
+    if !presses.is_empty() {
+        info!("Button presses this session: {}", presses.join(", "));
+    }
+    let write_times = stats.write_times();
+    if write_times.iter().any(|&(_, count)| count != 0) {
+        let buckets: Vec<String> = write_times
+            .iter()
+            .map(|(bucket, count)| format!("<{bucket} {count}"))
+            .collect();
+        info!("Gadget write times: {}", buckets.join(", "));
+    }
 
     Ok(())
 }
+
+/* 3. Forward, going back to the picker when the monitor asks for another
+ *    device or an interactive session's device goes away, until the user quits.
+ */
+fn forward(
+    mut forwarder: hid::Forwarder,
+    mut source: Source,
+    injections: Option<&inject::Injections>,
+    args: &cli::Args,
+    interactive: bool,
+    stats: &Stats,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    let stdout_taken = args.stdout_taken();
+    // The picker may switch gadgets between devices.
+    let mut config = args.config.clone();
+    loop {
+        let exit = if args.monitor && interactive {
+            monitor(forwarder, &source, stats, stop)?
+        } else {
+            notice(
+                stdout_taken,
+                "Starting HID forwarding. Press Ctrl+C to stop.",
+            );
+            forwarder.run(stats, Some(stop))?
+        };
+
+        match exit {
+            ForwarderExit::Quit => return Ok(()),
+            ForwarderExit::DeviceGone if !interactive => {
+                anyhow::bail!("Input device {source} disappeared")
+            }
+            ForwarderExit::DeviceGone => {
+                info!("Input device {source} disappeared, back to the device picker")
+            }
+            #[cfg(feature = "tui")]
+            ForwarderExit::Reselect => {}
+        }
+
+        let Some(path) = pick_device(&mut config.gadget)? else {
+            notice(stdout_taken, "No device selected. Exiting.");
+            return Ok(());
+        };
+        source = Source::Path(path);
+        notice(stdout_taken, &format!("Selected input device: {source}"));
+        forwarder = hid::Forwarder::open(&source, &config, stop)?;
+        if let Some(injections) = injections {
+            forwarder.set_injections(injections.clone());
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+fn pick_device(gadget: &mut PathBuf) -> anyhow::Result<Option<PathBuf>> {
+    Ok(tui::pick_device(gadget)?)
+}
+
+#[cfg(feature = "tui")]
+fn monitor(
+    forwarder: hid::Forwarder,
+    source: &Source,
+    stats: &Stats,
+    stop: &AtomicBool,
+) -> anyhow::Result<ForwarderExit> {
+    tui::monitor(forwarder, source, stats, stop)
+}
+
+// Built without the TUI: there is no picker, so the device must be given.
+#[cfg(not(feature = "tui"))]
+fn pick_device(_gadget: &mut PathBuf) -> anyhow::Result<Option<PathBuf>> {
+    anyhow::bail!("Built without the TUI device picker; choose a device with --input or --fd")
+}
+
+// Built without the TUI: there is no monitor, so forward headless.
+#[cfg(not(feature = "tui"))]
+fn monitor(
+    mut forwarder: hid::Forwarder,
+    _source: &Source,
+    stats: &Stats,
+    stop: &AtomicBool,
+) -> anyhow::Result<ForwarderExit> {
+    println!("Starting HID forwarding. Press Ctrl+C to stop.");
+    forwarder.run(stats, Some(stop))
+}
+
+// `hidex gadgets`: print each hidg node with what it declares itself as.
+fn list_gadgets() -> anyhow::Result<()> {
+    let gadgets = gadget::scan_gadgets().context("Failed to list /dev")?;
+    if gadgets.is_empty() {
+        println!("No /dev/hidg* nodes found; set up the USB HID gadget (configfs or g_hid) first");
+    }
+    for entry in gadgets {
+        let descriptor = entry.descriptor.as_deref().unwrap_or("descriptor unknown");
+        println!("{} ({descriptor})", entry.path.display());
+    }
+    Ok(())
+}
+
+// Status line for the user, moved to stderr when stdout carries reports.
+fn notice(stdout_taken: bool, message: &str) {
+    if stdout_taken {
+        eprintln!("{message}");
+    } else {
+        println!("{message}");
+    }
+}
+
+// Set `stop` on Ctrl+C / SIGTERM instead of dying mid-write.
+fn register_stop_signals(stop: &Arc<AtomicBool>) -> anyhow::Result<()> {
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(stop))
+            .context("Failed to install signal handler")?;
+    }
+    Ok(())
+}
+
+// Explicit -v/-vv/-q flags win over RUST_LOG; otherwise RUST_LOG applies, defaulting to info.
+fn init_logging(level: Option<LevelFilter>) {
+    let mut builder = match level {
+        Some(level) => {
+            let mut builder = env_logger::Builder::new();
+            builder.filter_level(level);
+            builder
+        }
+        None => env_logger::Builder::from_env(Env::default().default_filter_or("info")),
+    };
+    builder.init();
+}
@@ -0,0 +1,266 @@
+use std::{ops::Add, time::Duration};
+
+use crate::hid::Config;
+
+// Wheel speed, in detents per second, up to which detents are never boosted.
+const WHEEL_ACCEL_THRESHOLD: f32 = 10.0;
+
+// Detents further apart than this start a new flick.
+const WHEEL_ACCEL_RESET: Duration = Duration::from_millis(500);
+
+// Relative motion of one report: pointer counts and wheel detents.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Motion {
+    pub x: i32,
+    pub y: i32,
+    pub wheel: i32,
+    pub hwheel: i32,
+}
+
+impl Add for Motion {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            x: self.x.saturating_add(other.x),
+            y: self.y.saturating_add(other.y),
+            wheel: self.wheel.saturating_add(other.wheel),
+            hwheel: self.hwheel.saturating_add(other.hwheel),
+        }
+    }
+}
+
+// One transform of a report's motion. Stages may keep state across reports.
+type Stage = Box<dyn FnMut(Motion) -> Motion>;
+
+/* Ordered transforms applied to the motion of each report at flush time,
+ * before it is clamped into the report bytes. Stages are registered from
+ * the config, so a setting left at its default costs nothing.
+ */
+#[derive(Default)]
+pub struct MotionPipeline {
+    stages: Vec<Stage>,
+}
+
+impl MotionPipeline {
+    pub fn new(config: &Config) -> Self {
+        let mut pipeline = Self::default();
+        if config.hwheel_sign != 1 {
+            pipeline.push(hwheel_sign(config.hwheel_sign));
+        }
+        if config.scroll_step != 1.0 {
+            pipeline.push(scroll_step(config.scroll_step));
+        }
+        if config.quantize != (1, 1) {
+            pipeline.push(quantize(config.quantize));
+        }
+        pipeline
+    }
+
+    pub fn push(&mut self, stage: impl FnMut(Motion) -> Motion + 'static) {
+        self.stages.push(Box::new(stage));
+    }
+
+    pub fn apply(&mut self, motion: Motion) -> Motion {
+        self.stages
+            .iter_mut()
+            .fold(motion, |motion, stage| stage(motion))
+    }
+}
+
+// Flip horizontal scrolling for hosts that disagree with evdev on its sign.
+fn hwheel_sign(sign: i32) -> impl FnMut(Motion) -> Motion {
+    move |motion| Motion {
+        hwheel: motion.hwheel * sign,
+        ..motion
+    }
+}
+
+// Scale both wheels by the scroll step, carrying fractions to later reports.
+fn scroll_step(step: f32) -> impl FnMut(Motion) -> Motion {
+    let mut wheel = ScrollCarry::default();
+    let mut hwheel = ScrollCarry::default();
+    move |motion| Motion {
+        wheel: wheel.scale(motion.wheel, step),
+        hwheel: hwheel.scale(motion.hwheel, step),
+        ..motion
+    }
+}
+
+/* Emit pointer motion only in whole steps of `x`/`y` counts, so it snaps to
+ * a grid. What does not make a full step yet is carried to later reports,
+ * keeping the net motion.
+ */
+fn quantize((x, y): (i32, i32)) -> impl FnMut(Motion) -> Motion {
+    let (mut x_carry, mut y_carry) = (0, 0);
+    move |motion| Motion {
+        x: snap(motion.x, x, &mut x_carry),
+        y: snap(motion.y, y, &mut y_carry),
+        ..motion
+    }
+}
+
+// Whole steps of `delta` plus the carry, no more than fit into a report
+// byte, so clamping the report can't break the grid; the rest is carried.
+fn snap(delta: i32, step: i32, carry: &mut i32) -> i32 {
+    let total = delta.saturating_add(*carry);
+    let (low, high) = (i8::MIN as i32 / step * step, i8::MAX as i32 / step * step);
+    let snapped = (total / step * step).clamp(low, high);
+    *carry = total - snapped;
+    snapped
+}
+
+/* Scroll acceleration for one physical wheel: detents that follow each
+ * other faster than `WHEEL_ACCEL_THRESHOLD` per second are multiplied by
+ * (speed / threshold) ^ exponent, up to `cap`. Slow scrolling stays 1:1.
+ */
+pub struct WheelAccel {
+    exponent: f32,
+    cap: f32,
+    // Kernel timestamp of the previous detent.
+    last: Option<Duration>,
+    carry: ScrollCarry,
+}
+
+impl WheelAccel {
+    pub fn new(exponent: f32, cap: f32) -> Self {
+        Self {
+            exponent,
+            cap,
+            last: None,
+            carry: ScrollCarry::default(),
+        }
+    }
+
+    // `at` is the event's timestamp, so events read in one batch keep their spacing.
+    pub fn apply(&mut self, detents: i32, at: Duration) -> i32 {
+        let gap = self.last.map(|last| at.saturating_sub(last));
+        self.last = Some(at);
+        let factor = match gap {
+            Some(gap) if gap < WHEEL_ACCEL_RESET => {
+                let speed = detents.abs() as f32 / gap.as_secs_f32().max(f32::EPSILON);
+                (speed / WHEEL_ACCEL_THRESHOLD)
+                    .powf(self.exponent)
+                    .clamp(1.0, self.cap)
+            }
+            _ => {
+                self.carry = ScrollCarry::default();
+                1.0
+            }
+        };
+        self.carry.scale(detents, factor)
+    }
+}
+
+/* Scales wheel detents by the scroll step, keeping the fractional remainder.
+ * The remainder is dropped when the direction reverses, so scrolling back
+ * does not first pay off what was left over going the other way.
+ */
+#[derive(Default)]
+pub struct ScrollCarry {
+    remainder: f32,
+}
+
+impl ScrollCarry {
+    pub fn scale(&mut self, detents: i32, step: f32) -> i32 {
+        let scaled = detents as f32 * step;
+        if scaled * self.remainder < 0.0 {
+            self.remainder = 0.0;
+        }
+        let total = scaled + self.remainder;
+        let whole = total.trunc();
+        self.remainder = total - whole;
+        whole as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quantize_emits_whole_steps() {
+        let mut stage = quantize((4, 4));
+        let xs: Vec<i32> = (0..8)
+            .map(|_| {
+                stage(Motion {
+                    x: 1,
+                    ..Motion::default()
+                })
+                .x
+            })
+            .collect();
+        assert_eq!(xs, [0, 0, 0, 4, 0, 0, 0, 4]);
+    }
+
+    #[test]
+    fn snap_stays_on_the_grid_within_a_report() {
+        let mut carry = 0;
+        assert_eq!(snap(300, 10, &mut carry), 120);
+        assert_eq!(carry, 180);
+        assert_eq!(snap(0, 10, &mut carry), 120);
+        assert_eq!(snap(0, 10, &mut carry), 60);
+        assert_eq!(carry, 0);
+        assert_eq!(snap(-300, 4, &mut carry), -128);
+        assert_eq!(carry, -172);
+    }
+
+    #[test]
+    fn scroll_carry_keeps_fractions() {
+        let mut carry = ScrollCarry::default();
+        let detents: Vec<i32> = (0..4).map(|_| carry.scale(1, 0.5)).collect();
+        assert_eq!(detents, [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn pipeline_applies_stages_in_order() {
+        let motion = Motion {
+            x: 3,
+            hwheel: 2,
+            ..Motion::default()
+        };
+        let mut pipeline = MotionPipeline::new(&Config::default());
+        assert!(pipeline.apply(motion) == motion);
+
+        let config = Config {
+            hwheel_sign: -1,
+            scroll_step: 2.0,
+            ..Config::default()
+        };
+        let mut pipeline = MotionPipeline::new(&config);
+        pipeline.push(|motion| Motion {
+            x: motion.x * 10,
+            hwheel: motion.hwheel + 1,
+            ..motion
+        });
+        let out = pipeline.apply(motion);
+        assert_eq!((out.x, out.hwheel), (30, -3));
+    }
+
+    #[test]
+    fn wheel_accel_boosts_only_fast_flicks() {
+        let mut accel = WheelAccel::new(1.0, 4.0);
+        let ms = Duration::from_millis;
+        // First detent and slow scrolling (5/s) stay 1:1.
+        assert_eq!(accel.apply(1, ms(0)), 1);
+        assert_eq!(accel.apply(1, ms(200)), 1);
+        // 40 detents/s is four times the threshold.
+        assert_eq!(accel.apply(1, ms(225)), 4);
+        // 100/s would be 10x, held at the cap.
+        assert_eq!(accel.apply(-1, ms(235)), -4);
+        // A pause starts a new flick.
+        assert_eq!(accel.apply(1, ms(1000)), 1);
+    }
+
+    #[test]
+    fn scroll_carry_drops_on_reversal() {
+        let mut carry = ScrollCarry::default();
+        assert_eq!(carry.scale(1, 0.6), 0);
+        // Reversing starts from zero instead of paying off the 0.6 first.
+        assert_eq!(carry.scale(-1, 0.6), 0);
+        assert_eq!(carry.scale(-1, 0.6), -1);
+        // Same direction keeps the fraction.
+        assert_eq!(carry.scale(1, 0.6), 0);
+        assert_eq!(carry.scale(1, 0.6), 1);
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    mem,
+    time::{Duration, Instant},
+};
+
+/* Keeps a noisy log line readable: the first occurrence is logged right
+ * away, later ones are counted and summed up at most once per interval.
+ * With a threshold above one, an interval has to see that many
+ * occurrences before it is logged at all.
+ */
+pub struct RateLimiter {
+    interval: Duration,
+    threshold: u64,
+    // Start of the current interval and the occurrences within it.
+    window: Option<(Instant, u64)>,
+    // Whether the current interval has been logged.
+    logged: bool,
+    // Occurrences since the last log.
+    unlogged: u64,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self::with_threshold(interval, 1)
+    }
+
+    pub fn with_threshold(interval: Duration, threshold: u64) -> Self {
+        Self {
+            interval,
+            threshold,
+            window: None,
+            logged: false,
+            unlogged: 0,
+        }
+    }
+
+    // Count one occurrence; Some(occurrences since the last log) if it's time to log.
+    pub fn hit(&mut self, now: Instant) -> Option<u64> {
+        self.unlogged += 1;
+        match &mut self.window {
+            Some((start, count)) if now - *start < self.interval => *count += 1,
+            window => {
+                *window = Some((now, 1));
+                self.logged = false;
+            }
+        }
+        let count = self.window.map_or(0, |(_, count)| count);
+        if self.logged || count < self.threshold {
+            return None;
+        }
+        self.logged = true;
+        Some(mem::take(&mut self.unlogged))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_is_logged_and_the_rest_summed_up_per_interval() {
+        let mut limiter = RateLimiter::new(Duration::from_secs(1));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(limiter.hit(at(0)), Some(1));
+        assert_eq!(limiter.hit(at(10)), None);
+        assert_eq!(limiter.hit(at(500)), None);
+        assert_eq!(limiter.hit(at(1000)), Some(3));
+        assert_eq!(limiter.hit(at(1200)), None);
+    }
+
+    #[test]
+    fn threshold_ignores_occasional_occurrences() {
+        let mut limiter = RateLimiter::with_threshold(Duration::from_secs(1), 3);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(limiter.hit(at(0)), None);
+        assert_eq!(limiter.hit(at(2000)), None);
+        assert_eq!(limiter.hit(at(2100)), None);
+        // The third within one interval, reported with all since the last log.
+        assert_eq!(limiter.hit(at(2200)), Some(4));
+        assert_eq!(limiter.hit(at(2300)), None);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{collections::HashMap, str::FromStr};
+
+use anyhow::{Context, Result, bail};
+use evdev::KeyCode;
+
+use crate::report::{
+    BTN_MASK_6, BTN_MASK_7, BTN_MASK_8, BTN_MASK_EXTRA, BTN_MASK_LEFT, BTN_MASK_MIDDLE,
+    BTN_MASK_RIGHT, BTN_MASK_SIDE,
+};
+
+// What pressing a source button does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    // Hold a report button bit.
+    Button(u8),
+    // Scroll one detent in this direction on press, then again every
+    // repeat interval while held.
+    ScrollRepeat(Axis, i32),
+    // Scroll this many detents on press, one detent per repeat interval,
+    // whatever the wheels themselves do.
+    Wheel(Axis, i32),
+    // Swallow the button.
+    Disabled,
+}
+
+// Which wheel an action scrolls.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    Wheel,
+    // Horizontal wheel (AC Pan); positive scrolls right.
+    HWheel,
+}
+
+impl FromStr for Action {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let gesture = match s.split_once(':') {
+            Some(("wheel", delta)) => Some((Axis::Wheel, delta)),
+            Some(("hwheel", delta)) => Some((Axis::HWheel, delta)),
+            _ => None,
+        };
+        if let Some((axis, delta)) = gesture {
+            let delta = delta
+                .parse()
+                .ok()
+                .filter(|&delta| delta != 0)
+                .with_context(|| format!("Invalid wheel delta in '{s}' (expected e.g. wheel:5)"))?;
+            return Ok(Action::Wheel(axis, delta));
+        }
+
+        Ok(match s {
+            "left" => Action::Button(BTN_MASK_LEFT),
+            "right" => Action::Button(BTN_MASK_RIGHT),
+            "middle" => Action::Button(BTN_MASK_MIDDLE),
+            "side" => Action::Button(BTN_MASK_SIDE),
+            "extra" => Action::Button(BTN_MASK_EXTRA),
+            "button6" => Action::Button(BTN_MASK_6),
+            "button7" => Action::Button(BTN_MASK_7),
+            "button8" => Action::Button(BTN_MASK_8),
+            "scroll-up" => Action::ScrollRepeat(Axis::Wheel, 1),
+            "scroll-down" => Action::ScrollRepeat(Axis::Wheel, -1),
+            "pan-left" => Action::ScrollRepeat(Axis::HWheel, -1),
+            "pan-right" => Action::ScrollRepeat(Axis::HWheel, 1),
+            "none" => Action::Disabled,
+            _ => bail!(
+                "Unknown action '{s}' (expected left, right, middle, side, extra, \
+                 button6, button7, button8, scroll-up, scroll-down, pan-left, pan-right, wheel:N, hwheel:N or none)"
+            ),
+        })
+    }
+}
+
+/* Per-button overrides on top of the default mouse button layout, plus
+ * layers: while a layer's modifier button is held, its mappings take
+ * precedence. A modifier button only switches layers and sends nothing.
+ */
+#[derive(Clone, Default)]
+pub struct Remap {
+    actions: HashMap<KeyCode, Action>,
+    layers: HashMap<KeyCode, HashMap<KeyCode, Action>>,
+}
+
+impl Remap {
+    // Add a `BTN_CODE=action` mapping, replacing any earlier one for that code.
+    pub fn add(&mut self, spec: &str) -> Result<()> {
+        let (key, action) = mapping(spec)?;
+        self.actions.insert(key, action);
+        Ok(())
+    }
+
+    // Add a `MOD:BTN_CODE=action` mapping that applies while MOD is held.
+    pub fn add_layer(&mut self, spec: &str) -> Result<()> {
+        let (modifier, rest) = spec.split_once(':').with_context(|| {
+            format!("Invalid layer mapping '{spec}' (expected MOD:BTN_CODE=action)")
+        })?;
+        let modifier = key_code(modifier)?;
+        let (key, action) = mapping(rest)?;
+        self.layers.entry(modifier).or_default().insert(key, action);
+        Ok(())
+    }
+
+    // Whether anything differs from the default layout.
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty() && self.layers.is_empty()
+    }
+
+    pub fn is_layer_key(&self, key: KeyCode) -> bool {
+        self.layers.contains_key(&key)
+    }
+
+    pub fn action(&self, key: KeyCode) -> Option<Action> {
+        self.actions
+            .get(&key)
+            .copied()
+            .or_else(|| default_action(key))
+    }
+
+    // Action of `key` with `layer`'s modifier held, if any.
+    pub fn layer_action(&self, layer: Option<KeyCode>, key: KeyCode) -> Option<Action> {
+        layer
+            .and_then(|layer| self.layers.get(&layer)?.get(&key).copied())
+            .or_else(|| self.action(key))
+    }
+}
+
+// Parse `BTN_CODE=action`.
+fn mapping(spec: &str) -> Result<(KeyCode, Action)> {
+    let (code, action) = spec
+        .split_once('=')
+        .with_context(|| format!("Invalid remap '{spec}' (expected BTN_CODE=action)"))?;
+    Ok((key_code(code)?, action.parse()?))
+}
+
+fn key_code(code: &str) -> Result<KeyCode> {
+    KeyCode::from_str(code)
+        .ok()
+        .with_context(|| format!("Unknown key code '{code}'"))
+}
+
+/* Default layout: the five standard mouse buttons. Mice report their
+ * thumb buttons as either BTN_SIDE/BTN_EXTRA or BTN_BACK/BTN_FORWARD, so
+ * each pair shares a bit; remap one of them to tell them apart.
+ */
+fn default_action(key: KeyCode) -> Option<Action> {
+    let mask = match key {
+        KeyCode::BTN_LEFT => BTN_MASK_LEFT,
+        KeyCode::BTN_RIGHT => BTN_MASK_RIGHT,
+        KeyCode::BTN_MIDDLE => BTN_MASK_MIDDLE,
+        KeyCode::BTN_SIDE | KeyCode::BTN_BACK => BTN_MASK_SIDE,
+        KeyCode::BTN_EXTRA | KeyCode::BTN_FORWARD => BTN_MASK_EXTRA,
+        _ => return None,
+    };
+    Some(Action::Button(mask))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grouped_thumb_buttons_can_be_split() {
+        let mut remap = Remap::default();
+        assert_eq!(
+            remap.action(KeyCode::BTN_BACK),
+            remap.action(KeyCode::BTN_SIDE)
+        );
+        remap.add("BTN_BACK=button6").unwrap();
+        remap.add("BTN_FORWARD=button7").unwrap();
+        assert_eq!(
+            remap.action(KeyCode::BTN_BACK),
+            Some(Action::Button(BTN_MASK_6))
+        );
+        assert_eq!(
+            remap.action(KeyCode::BTN_FORWARD),
+            Some(Action::Button(BTN_MASK_7))
+        );
+        assert_eq!(
+            remap.action(KeyCode::BTN_SIDE),
+            Some(Action::Button(BTN_MASK_SIDE))
+        );
+        assert!(remap.add("BTN_BACK=button9").is_err());
+    }
+}
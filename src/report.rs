@@ -0,0 +1,21 @@
+/* Layout of the 5-byte mouse report written to the gadget:
+ * [buttons, x, y, wheel, hwheel]. The HID report descriptor configured on
+ * the gadget must match these offsets and button bits.
+ */
+
+// Length of one report in bytes.
+pub const REPORT_LEN: usize = 5;
+
+// Button bits in the first byte.
+pub const BTN_MASK_LEFT: u8 = 0x01;
+pub const BTN_MASK_RIGHT: u8 = 0x02;
+pub const BTN_MASK_MIDDLE: u8 = 0x04;
+pub const BTN_MASK_SIDE: u8 = 0x08;
+pub const BTN_MASK_EXTRA: u8 = 0x10;
+// Buttons 6-8 only reach the host if the gadget's descriptor declares
+// eight buttons instead of five plus padding.
+pub const BTN_MASK_6: u8 = 0x20;
+pub const BTN_MASK_7: u8 = 0x40;
+pub const BTN_MASK_8: u8 = 0x80;
+pub const BTN_MASK_ALL: u8 =
+    BTN_MASK_LEFT | BTN_MASK_RIGHT | BTN_MASK_MIDDLE | BTN_MASK_SIDE | BTN_MASK_EXTRA;
@@ -0,0 +1,201 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, warn};
+
+use crate::{inject::Injector, report::REPORT_LEN, stats::Stats};
+
+// How often idle connections and the accept loop check for shutdown.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Reply to an `inject` line that doesn't parse.
+const INJECT_USAGE: &str =
+    "expected inject BUTTONS X Y WHEEL HWHEEL, buttons 0 to 255 and deltas -128 to 127";
+
+/* Introspection socket. Each client gets one JSON line with the current
+ * stats on connect and another for every line it sends. With an injector,
+ * `inject B X Y W H` lines also send a report; otherwise it is read-only.
+ * The socket file is removed when the server is dropped.
+ */
+pub struct Server {
+    listener: UnixListener,
+    path: PathBuf,
+    injector: Option<Injector>,
+}
+
+impl Server {
+    pub fn bind(path: &Path, injector: Option<Injector>) -> Result<Self> {
+        // A leftover socket from a killed run refuses connections; a live one
+        // means another instance is still serving on it.
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                bail!("Socket {} is in use by another process", path.display());
+            }
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+        }
+
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind socket {}", path.display()))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+            injector,
+        })
+    }
+
+    // Serve clients until `stop` is set.
+    pub fn serve(&self, stats: &Stats, stop: &AtomicBool) {
+        thread::scope(|scope| {
+            while !stop.load(Ordering::Relaxed) {
+                match self.listener.accept() {
+                    Ok((stream, _)) => {
+                        let injector = self.injector.as_ref();
+                        scope.spawn(move || {
+                            if let Err(error) = serve_client(stream, injector, stats, stop) {
+                                debug!("Introspection client dropped: {error}");
+                            }
+                        });
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(STOP_POLL_INTERVAL);
+                    }
+                    Err(error) => {
+                        warn!("Introspection socket failed: {error}");
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn serve_client(
+    stream: UnixStream,
+    injector: Option<&Injector>,
+    stats: &Stats,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(STOP_POLL_INTERVAL))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut request = String::new();
+
+    writer.write_all(snapshot(stats).as_bytes())?;
+
+    while !stop.load(Ordering::Relaxed) {
+        match reader.read_line(&mut request) {
+            Ok(0) => break,
+            Ok(_) => {
+                let reply = match request.trim().strip_prefix("inject") {
+                    Some(report) => inject(injector, report).map(|()| snapshot(stats)),
+                    None => Ok(snapshot(stats)),
+                };
+                request.clear();
+                match reply {
+                    Ok(reply) => writer.write_all(reply.as_bytes())?,
+                    Err(error) => writeln!(writer, "{{\"error\":\"{error}\"}}")?,
+                }
+            }
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
+
+// Parse `B X Y W H` (button bits, then signed deltas) and send it as a report.
+fn inject(injector: Option<&Injector>, report: &str) -> Result<(), &'static str> {
+    let injector = injector.ok_or("injection is disabled, start hidex with --socket-inject")?;
+    let bytes = report_fields(report).ok_or(INJECT_USAGE)?;
+    injector
+        .send(bytes)
+        .map_err(|_| "failed to queue the report")
+}
+
+fn report_fields(report: &str) -> Option<[u8; REPORT_LEN]> {
+    let fields: Vec<&str> = report.split_whitespace().collect();
+    let [buttons, x, y, wheel, hwheel] = fields[..] else {
+        return None;
+    };
+    let delta = |raw: &str| raw.parse::<i8>().ok().map(|delta| delta as u8);
+    Some([
+        buttons.parse().ok()?,
+        delta(x)?,
+        delta(y)?,
+        delta(wheel)?,
+        delta(hwheel)?,
+    ])
+}
+
+// Current stats as one JSON line.
+fn snapshot(stats: &Stats) -> String {
+    let [buttons, x, y, wheel, hwheel] = stats.last_report();
+    let presses: Vec<String> = stats
+        .presses()
+        .iter()
+        .map(|(name, count)| format!("\"{name}\":{count}"))
+        .collect();
+    let write_times: Vec<String> = stats
+        .write_times()
+        .iter()
+        .map(|(bucket, count)| format!("\"{bucket}\":{count}"))
+        .collect();
+    format!(
+        "{{\"events\":{},\"reports\":{},\"dropped\":{},\"rate\":{:.1},\
+         \"report\":{{\"buttons\":{},\"x\":{},\"y\":{},\"wheel\":{},\"hwheel\":{}}},\
+         \"presses\":{{{}}},\"write_times\":{{{}}}}}\n",
+        stats.events.load(Ordering::Relaxed),
+        stats.reports.load(Ordering::Relaxed),
+        stats.dropped.load(Ordering::Relaxed),
+        stats.report_rate(),
+        buttons,
+        x as i8,
+        y as i8,
+        wheel as i8,
+        hwheel as i8,
+        presses.join(","),
+        write_times.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_fields_parse_buttons_and_signed_deltas() {
+        assert_eq!(report_fields("1 10 -10 0 0"), Some([1, 10, 0xf6, 0, 0]));
+        assert_eq!(
+            report_fields("  255 127 -128 1 -1 "),
+            Some([0xff, 127, 0x80, 1, 0xff])
+        );
+        assert_eq!(report_fields("1 128 0 0 0"), None);
+        assert_eq!(report_fields("256 0 0 0 0"), None);
+        assert_eq!(report_fields("1 0 0 0"), None);
+        assert_eq!(report_fields("1 0 0 0 0 0"), None);
+        assert_eq!(report_fields("left 0 0 0 0"), None);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::report::REPORT_LEN;
+
+// Report buttons by bit, named as in --remap.
+const BUTTON_NAMES: [&str; 8] = [
+    "left", "right", "middle", "side", "extra", "button6", "button7", "button8",
+];
+
+// Upper bounds of the write time histogram buckets; the last is open-ended.
+const WRITE_TIME_BUCKETS: [(Duration, &str); 8] = [
+    (Duration::from_micros(250), "250us"),
+    (Duration::from_micros(500), "500us"),
+    (Duration::from_millis(1), "1ms"),
+    (Duration::from_millis(2), "2ms"),
+    (Duration::from_millis(5), "5ms"),
+    (Duration::from_millis(10), "10ms"),
+    (Duration::from_millis(20), "20ms"),
+    (Duration::MAX, "inf"),
+];
+
+// Window over which the report rate is averaged.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+// Counters shared between the forwarding loop and anything observing it.
+#[derive(Default)]
+pub struct Stats {
+    pub events: AtomicU64,
+    pub reports: AtomicU64,
+    // Reports dropped because the gadget could not take them fast enough.
+    pub dropped: AtomicU64,
+    // Timed gadget writes (--slow-write-us) per WRITE_TIME_BUCKETS bucket.
+    write_times: [AtomicU64; 8],
+    // Presses sent to the host, per report button bit.
+    presses: [AtomicU64; 8],
+    // Last written report, packed little-endian into the low bytes.
+    last_report: AtomicU64,
+    rate: Mutex<RateWindow>,
+}
+
+// Report count at the start of the current rate window.
+#[derive(Default)]
+struct RateWindow {
+    started: Option<(Instant, u64)>,
+    rate: f64,
+}
+
+impl Stats {
+    pub fn record_report(&self, bytes: [u8; REPORT_LEN]) {
+        let mut packed = [0u8; 8];
+        packed[..REPORT_LEN].copy_from_slice(&bytes);
+        self.last_report
+            .store(u64::from_le_bytes(packed), Ordering::Relaxed);
+        self.reports.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_write_time(&self, took: Duration) {
+        let bucket = WRITE_TIME_BUCKETS
+            .iter()
+            .position(|&(bound, _)| took < bound)
+            .unwrap_or(WRITE_TIME_BUCKETS.len() - 1);
+        self.write_times[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Write counts by histogram bucket label, fastest first.
+    pub fn write_times(&self) -> [(&'static str, u64); 8] {
+        let mut times = [("", 0); 8];
+        for (bucket, time) in times.iter_mut().enumerate() {
+            *time = (
+                WRITE_TIME_BUCKETS[bucket].1,
+                self.write_times[bucket].load(Ordering::Relaxed),
+            );
+        }
+        times
+    }
+
+    pub fn add_presses(&self, presses: [u64; 8]) {
+        for (total, count) in self.presses.iter().zip(presses) {
+            if count != 0 {
+                total.fetch_add(count, Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Press counts by button name, in bit order.
+    pub fn presses(&self) -> [(&'static str, u64); 8] {
+        let mut presses = [("", 0); 8];
+        for (bit, press) in presses.iter_mut().enumerate() {
+            *press = (BUTTON_NAMES[bit], self.presses[bit].load(Ordering::Relaxed));
+        }
+        presses
+    }
+
+    pub fn last_report(&self) -> [u8; REPORT_LEN] {
+        let packed = self.last_report.load(Ordering::Relaxed).to_le_bytes();
+        let mut bytes = [0u8; REPORT_LEN];
+        bytes.copy_from_slice(&packed[..REPORT_LEN]);
+        bytes
+    }
+
+    /* Reports per second over the last completed window. Observers drive
+     * the sampling, so this is only as fresh as the most frequent caller.
+     */
+    pub fn report_rate(&self) -> f64 {
+        let mut window = self
+            .rate
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let reports = self.reports.load(Ordering::Relaxed);
+        let now = Instant::now();
+
+        match window.started {
+            Some((at, sampled)) if now - at >= RATE_WINDOW => {
+                window.rate = (reports - sampled) as f64 / (now - at).as_secs_f64();
+                window.started = Some((now, reports));
+            }
+            Some(_) => {}
+            None => window.started = Some((now, reports)),
+        }
+
+        window.rate
+    }
+}
@@ -0,0 +1,174 @@
+use std::time::{Duration, Instant};
+
+use evdev::{AbsoluteAxisCode, Device};
+
+use crate::{hid::Config, motion::Motion};
+
+// How often a deflected stick moves the pointer.
+pub const STICK_TICK: Duration = Duration::from_millis(10);
+
+/* An analog stick driving the pointer by velocity: the further it is
+ * pushed, the faster the pointer moves, and it keeps moving while the
+ * stick is held. Deflection inside the deadzone counts as centred.
+ */
+pub struct Stick {
+    x: StickAxis,
+    y: StickAxis,
+    // Fraction of the deflection that is ignored around the centre.
+    deadzone: f32,
+    // Pointer counts per tick at full deflection.
+    max_speed: f32,
+    // When the pointer moves next; None while the stick is centred.
+    next: Option<Instant>,
+}
+
+// One stick axis: its range as reported by the device and its position.
+struct StickAxis {
+    center: f32,
+    half_range: f32,
+    value: i32,
+    // Fraction of a pointer count left over from earlier ticks.
+    carry: f32,
+}
+
+impl Stick {
+    // None if the device has no ABS_X/ABS_Y to read the ranges of.
+    pub fn from_device(device: &Device, config: &Config) -> Option<Self> {
+        let (mut x, mut y) = (None, None);
+        for (axis, info) in device.get_absinfo().ok()? {
+            let range = StickAxis::new(info.minimum(), info.maximum());
+            match axis {
+                AbsoluteAxisCode::ABS_X => x = Some(range),
+                AbsoluteAxisCode::ABS_Y => y = Some(range),
+                _ => {}
+            }
+        }
+        Some(Self {
+            x: x?,
+            y: y?,
+            deadzone: config.stick_deadzone,
+            max_speed: config.stick_speed,
+            next: None,
+        })
+    }
+
+    // Record a stick position; false if `code` is not a stick axis.
+    pub fn set(&mut self, code: AbsoluteAxisCode, value: i32, now: Instant) -> bool {
+        match code {
+            AbsoluteAxisCode::ABS_X => self.x.value = value,
+            AbsoluteAxisCode::ABS_Y => self.y.value = value,
+            _ => return false,
+        }
+        // Start moving right away when the stick leaves the deadzone.
+        if self.next.is_none() && self.deflected() {
+            self.next = Some(now);
+        }
+        true
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.next
+    }
+
+    // Pointer motion for a tick that is due, if any.
+    pub fn tick(&mut self, now: Instant) -> Option<Motion> {
+        if self.next? > now {
+            return None;
+        }
+        if !self.deflected() {
+            self.next = None;
+            self.x.carry = 0.0;
+            self.y.carry = 0.0;
+            return None;
+        }
+        self.next = Some(now + STICK_TICK);
+        let (deadzone, max_speed) = (self.deadzone, self.max_speed);
+        Some(Motion {
+            x: self.x.step(deadzone, max_speed),
+            y: self.y.step(deadzone, max_speed),
+            ..Motion::default()
+        })
+    }
+
+    // Forget the position, e.g. after a resync; the next event sets it again.
+    pub fn center(&mut self) {
+        for axis in [&mut self.x, &mut self.y] {
+            axis.value = axis.center as i32;
+            axis.carry = 0.0;
+        }
+        self.next = None;
+    }
+
+    fn deflected(&self) -> bool {
+        self.x.speed(self.deadzone) != 0.0 || self.y.speed(self.deadzone) != 0.0
+    }
+}
+
+impl StickAxis {
+    fn new(minimum: i32, maximum: i32) -> Self {
+        let center = (minimum as f32 + maximum as f32) / 2.0;
+        Self {
+            center,
+            half_range: ((maximum as f32 - minimum as f32) / 2.0).max(1.0),
+            value: center as i32,
+            carry: 0.0,
+        }
+    }
+
+    /* Speed as a fraction of the maximum, -1 to 1. The deflection beyond the
+     * deadzone is rescaled to start from zero and squared, for fine control
+     * near the centre and full speed at the edge.
+     */
+    fn speed(&self, deadzone: f32) -> f32 {
+        let deflection = ((self.value as f32 - self.center) / self.half_range).clamp(-1.0, 1.0);
+        let beyond = (deflection.abs() - deadzone).max(0.0) / (1.0 - deadzone);
+        deflection.signum() * beyond * beyond
+    }
+
+    // Whole pointer counts for one tick, carrying the fraction.
+    fn step(&mut self, deadzone: f32, max_speed: f32) -> i32 {
+        let total = self.speed(deadzone) * max_speed + self.carry;
+        let whole = total.trunc();
+        self.carry = total - whole;
+        whole as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_ignores_the_deadzone_and_grows_quadratically() {
+        let mut axis = StickAxis::new(-100, 100);
+        let mut speed = |value| {
+            axis.value = value;
+            axis.speed(0.1)
+        };
+        assert_eq!(speed(0), 0.0);
+        assert_eq!(speed(10), 0.0);
+        assert!((speed(55) - 0.25).abs() < 1e-6);
+        assert!((speed(-55) + 0.25).abs() < 1e-6);
+        assert_eq!(speed(100), 1.0);
+        assert_eq!(speed(-1000), -1.0);
+    }
+
+    #[test]
+    fn deflected_stick_moves_every_tick_until_centred() {
+        let mut stick = Stick {
+            x: StickAxis::new(-100, 100),
+            y: StickAxis::new(-100, 100),
+            deadzone: 0.0,
+            max_speed: 8.0,
+            next: None,
+        };
+        let now = Instant::now();
+        assert!(stick.set(AbsoluteAxisCode::ABS_X, 100, now));
+        assert!(!stick.set(AbsoluteAxisCode::ABS_Z, 100, now));
+        assert_eq!(stick.tick(now).map(|motion| motion.x), Some(8));
+        assert_eq!(stick.next_deadline(), Some(now + STICK_TICK));
+        assert!(stick.set(AbsoluteAxisCode::ABS_X, 0, now));
+        assert!(stick.tick(now + STICK_TICK).is_none());
+        assert_eq!(stick.next_deadline(), None);
+    }
+}
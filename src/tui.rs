@@ -1,166 +1,346 @@
-use std::{fs, io, path::PathBuf};
+use std::{
+    io::{self, Write},
+    panic,
+    path::{Path, PathBuf},
+    sync::{
+        Once,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::{self, ScopedJoinHandle},
+    time::{Duration, Instant},
+};
 
 use crossterm::{
+    cursor,
     event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
     execute,
-    terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
+    terminal::{
+        EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+        is_raw_mode_enabled,
+    },
 };
-use evdev::Device;
 use ratatui::{
     Terminal,
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Flex, Layout, Rect},
     style::{Modifier, Style},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
+};
+
+use crate::{
+    gadget::{self, GadgetEntry, NodeState},
+    hid::{Forwarder, ForwarderExit},
+    input::{DeviceEntry, Source, scan_devices},
+    report::{
+        BTN_MASK_6, BTN_MASK_7, BTN_MASK_8, BTN_MASK_EXTRA, BTN_MASK_LEFT, BTN_MASK_MIDDLE,
+        BTN_MASK_RIGHT, BTN_MASK_SIDE,
+    },
+    stats::Stats,
 };
 
-const INPUT_DIR: &str = "/dev/input";
+// Redraw interval of the monitor view.
+const MONITOR_TICK: Duration = Duration::from_millis(50);
+
+// How often the picker looks for devices that appeared or went away.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(1);
+
+// Button bits of the mouse report, as labelled in the monitor.
+const BUTTON_LABELS: [(u8, &str); 8] = [
+    (BTN_MASK_LEFT, "L"),
+    (BTN_MASK_RIGHT, "R"),
+    (BTN_MASK_MIDDLE, "M"),
+    (BTN_MASK_SIDE, "Side"),
+    (BTN_MASK_EXTRA, "Extra"),
+    (BTN_MASK_6, "6"),
+    (BTN_MASK_7, "7"),
+    (BTN_MASK_8, "8"),
+];
+
+// Key bindings shown by the help overlay, by screen.
+const HELP: [(&str, &[(&str, &str)]); 2] = [
+    (
+        "Device picker",
+        &[
+            ("↑/↓", "Move the selection"),
+            ("Enter", "Forward the selected device"),
+            ("g", "Switch to the next /dev/hidg* gadget"),
+            ("r", "Rescan devices now (also done every second)"),
+            ("?", "Show or hide this help"),
+            ("q, Ctrl+C", "Quit"),
+        ],
+    ),
+    (
+        "Monitor (while forwarding)",
+        &[
+            ("p", "Stop and go back to the device picker"),
+            (
+                "r",
+                "Soft reset: release all buttons, clear carries and timers",
+            ),
+            ("?", "Show or hide this help"),
+            ("q, Ctrl+C", "Stop forwarding and quit"),
+        ],
+    ),
+];
 
 type Backend = CrosstermBackend<io::Stdout>;
 type Term = Terminal<Backend>;
 
-// Single entry in the device list shown in the TUI.
-#[derive(Clone)]
-struct DeviceEntry {
-    path: PathBuf,
-    name: String,
-}
-
 // Application state for the device picker.
 struct App {
     devices: Vec<DeviceEntry>,
     selected: usize,
+    // The gadget node the reports will go to and its pre-flight state.
+    output: PathBuf,
+    gadget: NodeState,
+    // Gadget nodes to cycle through, always including `output`.
+    gadgets: Vec<GadgetEntry>,
+    // The help overlay is open and takes the keys.
+    help: bool,
 }
 
 impl App {
-    fn new() -> io::Result<Self> {
+    fn new(output: &Path) -> io::Result<Self> {
         Ok(Self {
             devices: scan_devices()?,
             selected: 0,
+            output: output.to_path_buf(),
+            gadget: gadget::probe_node(output),
+            gadgets: scan_gadgets(output),
+            help: false,
         })
     }
 
-    fn refresh(&mut self) -> io::Result<()> {
-        self.devices = scan_devices()?;
-        if self.selected >= self.devices.len() {
-            self.selected = self.devices.len().saturating_sub(1);
+    /* Rescan devices and gadget nodes. The selection follows its device
+     * when others come or go. Returns whether anything changed.
+     */
+    fn refresh(&mut self) -> io::Result<bool> {
+        let devices = scan_devices()?;
+        let gadget = gadget::probe_node(&self.output);
+        let gadgets = scan_gadgets(&self.output);
+        if devices == self.devices && gadget == self.gadget && gadgets == self.gadgets {
+            return Ok(false);
         }
-        Ok(())
+
+        let selected = self.selected_device().map(|device| device.path.clone());
+        self.devices = devices;
+        self.gadget = gadget;
+        self.gadgets = gadgets;
+        self.selected = selected
+            .and_then(|path| self.devices.iter().position(|device| device.path == path))
+            .unwrap_or(self.selected.min(self.devices.len().saturating_sub(1)));
+        Ok(true)
     }
 
     fn selected_device(&self) -> Option<&DeviceEntry> {
         self.devices.get(self.selected)
     }
+
+    // Switch the output to the next gadget node.
+    fn next_gadget(&mut self) {
+        let current = self
+            .gadgets
+            .iter()
+            .position(|entry| entry.path == self.output);
+        let next = current.map_or(0, |index| (index + 1) % self.gadgets.len());
+        if let Some(entry) = self.gadgets.get(next) {
+            self.output = entry.path.clone();
+            self.gadget = gadget::probe_node(&self.output);
+        }
+    }
+
+    fn output_descriptor(&self) -> Option<&str> {
+        self.gadgets
+            .iter()
+            .find(|entry| entry.path == self.output)
+            .and_then(|entry| entry.descriptor.as_deref())
+    }
+}
+
+// The hidg nodes present, plus `output` even while it is missing.
+fn scan_gadgets(output: &Path) -> Vec<GadgetEntry> {
+    let mut gadgets = gadget::scan_gadgets().unwrap_or_default();
+    if !gadgets.iter().any(|entry| entry.path == output) {
+        gadgets.push(GadgetEntry {
+            path: output.to_path_buf(),
+            descriptor: None,
+        });
+        gadgets.sort_by(|a, b| a.path.cmp(&b.path));
+    }
+    gadgets
 }
 
 /* Public entry point: run the TUI picker and return the chosen device.
+ * `gadget` starts as the output to show and is updated to the one picked
+ * with 'g', whether or not a device is selected.
  * Returns:
  * - Ok(Some(path)) if the user selected a device
  * - Ok(None) if the user pressed 'q' to quit
  */
-pub fn pick_device() -> io::Result<Option<PathBuf>> {
-    // Enter raw mode and the alternate screen
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
-    let backend = Backend::new(stdout);
-    let mut terminal = Term::new(backend)?;
-
-    // Use an inner closure so we can always restore the terminal afterwards.
-    let result = (|| {
-        let mut app = App::new()?;
-        run(&mut terminal, &mut app)
-    })();
-
-    // Always try to restore terminal state, even if the app failed.
-    disable_raw_mode()?;
-    execute!(io::stdout(), LeaveAlternateScreen)?;
-    terminal.show_cursor()?;
+pub fn pick_device(gadget: &mut PathBuf) -> io::Result<Option<PathBuf>> {
+    with_terminal(|terminal| {
+        let mut app = App::new(gadget)?;
+        let picked = run(terminal, &mut app);
+        *gadget = app.output;
+        picked
+    })
+}
+
+/* Forward events on a worker thread while showing the reports live.
+ * Returns once the user quits or asks for another device, `stop` is set
+ * elsewhere, or the forwarder ends on its own; the forwarder is stopped
+ * first, which ungrabs the device.
+ */
+pub fn monitor(
+    mut forwarder: Forwarder,
+    source: &Source,
+    stats: &Stats,
+    stop: &AtomicBool,
+) -> anyhow::Result<ForwarderExit> {
+    let target = forwarder.target().to_string();
+    let reset = forwarder.reset_flag();
+    // Stops just this forwarder; `stop` ends the whole program.
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let worker = scope.spawn(|| forwarder.run(stats, Some(&done)));
+        let ui = with_terminal(|terminal| {
+            run_monitor(terminal, source, &target, stats, stop, &reset, &worker)
+        });
+
+        done.store(true, Ordering::Relaxed);
+        let forwarded = worker
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+
+        // An unplugged device outranks whatever the UI saw.
+        match (forwarded, ui?) {
+            (ForwarderExit::DeviceGone, _) => Ok(ForwarderExit::DeviceGone),
+            (_, exit) => Ok(exit),
+        }
+    })
+}
+
+// Run `session` on the alternate screen in raw mode, restoring the terminal afterwards.
+fn with_terminal<T>(session: impl FnOnce(&mut Term) -> io::Result<T>) -> io::Result<T> {
+    install_panic_hook();
+
+    // From here on the terminal is restored however the session ends.
+    let guard = TerminalGuard::enter(io::stdout())?;
+    let mut terminal = Term::new(Backend::new(io::stdout()))?;
+    let result = session(&mut terminal);
+    drop(guard);
 
     result
 }
 
-// Main TUI loop.
-fn run(terminal: &mut Term, app: &mut App) -> io::Result<Option<PathBuf>> {
-    loop {
-        // Draw the UI
-        terminal.draw(|frame| {
-            let chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints([
-                    Constraint::Length(3),
-                    Constraint::Min(1),
-                    Constraint::Length(1),
-                ])
-                .split(frame.area());
+/* Raw mode and the alternate screen for as long as the guard lives.
+ * Dropping it puts the terminal back, whether the session returned, its
+ * setup failed halfway or a panic is unwinding through it.
+ */
+struct TerminalGuard<W: Write> {
+    out: W,
+}
 
-            // Header
-            let header = Paragraph::new("Select an input device").block(
-                Block::default()
-                    .title("Device Picker")
-                    .borders(Borders::ALL),
-            );
-            frame.render_widget(header, chunks[0]);
+impl<W: Write> TerminalGuard<W> {
+    fn enter(out: W) -> io::Result<Self> {
+        enable_raw_mode()?;
+        let mut guard = Self { out };
+        execute!(guard.out, EnterAlternateScreen)?;
+        Ok(guard)
+    }
+}
 
-            // Device list
-            let items: Vec<ListItem> = if app.devices.is_empty() {
-                vec![ListItem::new("No /dev/input/event* devices found")]
-            } else {
-                app.devices
-                    .iter()
-                    .map(|device| {
-                        let text = format!("{} ({})", device.path.display(), device.name);
-                        ListItem::new(text)
-                    })
-                    .collect()
-            };
-
-            let mut state = ListState::default();
-            if !app.devices.is_empty() {
-                state.select(Some(app.selected));
-            }
+impl<W: Write> Drop for TerminalGuard<W> {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(self.out, LeaveAlternateScreen, cursor::Show);
+    }
+}
 
-            let list = List::new(items)
-                .block(Block::default().title("/dev/input").borders(Borders::ALL))
-                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+/* The guard restores the terminal only after the panic message has been
+ * printed, to the alternate screen, where it would vanish. Restoring it
+ * first from the hook keeps the message on the normal screen.
+ */
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if is_raw_mode_enabled().unwrap_or(false) {
+                let _ = disable_raw_mode();
+                let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+            }
+            previous(info);
+        }));
+    });
+}
 
-            frame.render_stateful_widget(list, chunks[1], &mut state);
+// Main TUI loop.
+fn run(terminal: &mut Term, app: &mut App) -> io::Result<Option<PathBuf>> {
+    let mut dirty = true;
+    let mut next_scan = Instant::now() + RESCAN_INTERVAL;
+    loop {
+        // Draw the UI, only when something changed
+        if dirty {
+            draw_picker(terminal, app)?;
+            dirty = false;
+        }
 
-            // Footer
-            let footer_text = "↑/↓: move  Enter: select  r: refresh  q: quit";
-            let footer = Paragraph::new(footer_text);
-            frame.render_widget(footer, chunks[2]);
-        })?;
+        // Rescan on a timer, waiting for keys in between
+        let now = Instant::now();
+        if now >= next_scan {
+            dirty = app.refresh()?;
+            next_scan = now + RESCAN_INTERVAL;
+            continue;
+        }
+        if !event::poll(next_scan - now)? {
+            continue;
+        }
 
         // Handle input
         if let Event::Key(key) = event::read()? {
             if key.kind != KeyEventKind::Press {
                 continue;
             }
+            dirty = true;
+
+            // The overlay swallows everything but closing it and Ctrl+C
+            if app.help {
+                match key.code {
+                    KeyCode::Char('?') | KeyCode::Esc => app.help = false,
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        return Ok(None);
+                    }
+                    _ => {}
+                }
+                continue;
+            }
 
             match key.code {
                 KeyCode::Char('q') => return Ok(None),
                 KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                     return Ok(None);
                 }
-                KeyCode::Char('r') => app.refresh()?,
-
-                KeyCode::Up => {
-                    if !app.devices.is_empty() {
-                        if app.selected == 0 {
-                            app.selected = app.devices.len() - 1;
-                        } else {
-                            app.selected -= 1;
-                        }
-                    }
+                KeyCode::Char('r') => {
+                    app.refresh()?;
+                    next_scan = Instant::now() + RESCAN_INTERVAL;
                 }
+                KeyCode::Char('g') => app.next_gadget(),
+                KeyCode::Char('?') => app.help = true,
 
-                KeyCode::Down => {
-                    if !app.devices.is_empty() {
-                        app.selected = (app.selected + 1) % app.devices.len();
+                KeyCode::Up if !app.devices.is_empty() => {
+                    if app.selected == 0 {
+                        app.selected = app.devices.len() - 1;
+                    } else {
+                        app.selected -= 1;
                     }
                 }
 
+                KeyCode::Down if !app.devices.is_empty() => {
+                    app.selected = (app.selected + 1) % app.devices.len();
+                }
+
                 KeyCode::Enter => {
                     if let Some(device) = app.selected_device() {
                         return Ok(Some(device.path.clone()));
@@ -169,31 +349,251 @@ fn run(terminal: &mut Term, app: &mut App) -> io::Result<Option<PathBuf>> {
 
                 _ => {}
             }
+        } else {
+            // Resizes and the like need a full redraw
+            dirty = true;
         }
     }
 }
 
-// Scan /dev/input/event* and collect their names.
-fn scan_devices() -> io::Result<Vec<DeviceEntry>> {
-    let mut devices = Vec::new();
+fn draw_picker(terminal: &mut Term, app: &App) -> io::Result<()> {
+    terminal.draw(|frame| {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Min(1),
+                Constraint::Length(2),
+            ])
+            .split(frame.area());
 
-    for entry in fs::read_dir(INPUT_DIR)? {
-        let entry = entry?;
-        let path = entry.path();
+        // Header
+        let header = Paragraph::new("Select an input device").block(
+            Block::default()
+                .title("Device Picker")
+                .borders(Borders::ALL),
+        );
+        frame.render_widget(header, chunks[0]);
 
-        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
-            continue;
+        // Device list
+        let items: Vec<ListItem> = if app.devices.is_empty() {
+            vec![ListItem::new("No /dev/input/event* devices found")]
+        } else {
+            app.devices
+                .iter()
+                .map(|device| {
+                    let text = format!("{} ({})", device.path.display(), device.name);
+                    ListItem::new(text)
+                })
+                .collect()
         };
-        if !file_name.starts_with("event") {
-            continue;
+
+        let mut state = ListState::default();
+        if !app.devices.is_empty() {
+            state.select(Some(app.selected));
         }
 
-        if let Ok(dev) = Device::open(&path) {
-            let name = dev.name().unwrap_or("Unknown device").to_string();
-            devices.push(DeviceEntry { path, name });
+        let list = List::new(items)
+            .block(Block::default().title("/dev/input").borders(Borders::ALL))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+
+        frame.render_stateful_widget(list, chunks[1], &mut state);
+
+        // Footer: gadget status above the key help
+        let output = match app.output_descriptor() {
+            Some(descriptor) => format!("Gadget {} ({descriptor})", app.output.display()),
+            None => format!("Gadget {}", app.output.display()),
+        };
+        let gadget = match app.gadget {
+            NodeState::Ready => Span::raw(format!("{output}: ready")),
+            NodeState::Missing => Span::styled(
+                format!("{output}: missing, set up the USB HID gadget (configfs or g_hid) first"),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            NodeState::NotWritable => Span::styled(
+                format!("{output}: not writable, run as root"),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+        };
+        let keys = if app.gadgets.len() > 1 {
+            "↑/↓: move  Enter: select  g: next gadget  r: refresh  ?: help  q: quit"
+        } else {
+            "↑/↓: move  Enter: select  r: refresh  ?: help  q: quit"
+        };
+        let footer = Paragraph::new(vec![Line::from(gadget), Line::from(keys)]);
+        frame.render_widget(footer, chunks[2]);
+
+        if app.help {
+            draw_help(frame);
+        }
+    })?;
+    Ok(())
+}
+
+// Key bindings in a box centered over whatever is on screen.
+fn draw_help(frame: &mut ratatui::Frame) {
+    let key_width = HELP
+        .iter()
+        .flat_map(|(_, keys)| keys.iter())
+        .map(|(key, _)| key.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut lines = Vec::new();
+    for (screen, keys) in HELP {
+        if !lines.is_empty() {
+            lines.push(Line::from(""));
+        }
+        lines.push(Line::styled(
+            screen,
+            Style::default().add_modifier(Modifier::BOLD),
+        ));
+        for (key, description) in keys {
+            lines.push(Line::from(format!("  {key:<key_width$}  {description}")));
+        }
+    }
+
+    let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 4;
+    let area = centered(frame.area(), width, lines.len() as u16 + 2);
+    let help = Paragraph::new(lines).block(
+        Block::default()
+            .title("Help (? or Esc to close)")
+            .borders(Borders::ALL),
+    );
+    frame.render_widget(Clear, area);
+    frame.render_widget(help, area);
+}
+
+// A `width` x `height` rectangle in the middle of `area`, clipped to it.
+fn centered(area: Rect, width: u16, height: u16) -> Rect {
+    let [area] = Layout::vertical([Constraint::Length(height)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [area] = Layout::horizontal([Constraint::Length(width)])
+        .flex(Flex::Center)
+        .areas(area);
+    area
+}
+
+// Live view of the reports written by the forwarder.
+fn run_monitor(
+    terminal: &mut Term,
+    source: &Source,
+    target: &str,
+    stats: &Stats,
+    stop: &AtomicBool,
+    reset: &AtomicBool,
+    worker: &ScopedJoinHandle<'_, anyhow::Result<ForwarderExit>>,
+) -> io::Result<ForwarderExit> {
+    // The help overlay is open and takes the keys.
+    let mut help = false;
+    loop {
+        // The forwarder stopped on its own (e.g. an I/O error); let the caller report it.
+        if worker.is_finished() || stop.load(Ordering::Relaxed) {
+            return Ok(ForwarderExit::Quit);
+        }
+
+        let rate = stats.report_rate();
+
+        terminal.draw(|frame| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(3),
+                    Constraint::Min(1),
+                    Constraint::Length(1),
+                ])
+                .split(frame.area());
+
+            // Header
+            let header = Paragraph::new(format!("Forwarding {source}"))
+                .block(Block::default().title("Monitor").borders(Borders::ALL));
+            frame.render_widget(header, chunks[0]);
+
+            // Last report
+            let [buttons, x, y, wheel, hwheel] = stats.last_report();
+            let mut button_spans = vec![Span::raw("Buttons: ")];
+            for (mask, label) in BUTTON_LABELS {
+                let style = if buttons & mask != 0 {
+                    Style::default().add_modifier(Modifier::REVERSED)
+                } else {
+                    Style::default()
+                };
+                button_spans.push(Span::styled(format!(" {label} "), style));
+                button_spans.push(Span::raw(" "));
+            }
+
+            let mut lines = vec![
+                Line::from(button_spans),
+                Line::from(format!(
+                    "X: {:4}  Y: {:4}  Wheel: {:4}  HWheel: {:4}",
+                    x as i8, y as i8, wheel as i8, hwheel as i8
+                )),
+                Line::from(""),
+                Line::from(format!(
+                    "Reports: {}  Events: {}  Rate: {rate:.0}/s",
+                    stats.reports.load(Ordering::Relaxed),
+                    stats.events.load(Ordering::Relaxed)
+                )),
+            ];
+            let dropped = stats.dropped.load(Ordering::Relaxed);
+            if dropped > 0 {
+                lines.push(Line::styled(
+                    format!("Dropped: {dropped} (the host can't keep up)"),
+                    Style::default().add_modifier(Modifier::BOLD),
+                ));
+            }
+            let body =
+                Paragraph::new(lines).block(Block::default().title(target).borders(Borders::ALL));
+            frame.render_widget(body, chunks[1]);
+
+            // Footer
+            frame.render_widget(
+                Paragraph::new("q: stop forwarding  p: pick another device  r: reset  ?: help"),
+                chunks[2],
+            );
+
+            if help {
+                draw_help(frame);
+            }
+        })?;
+
+        // Handle input without blocking the redraw
+        if event::poll(MONITOR_TICK)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            // As in the picker, the overlay only lets through closing it and Ctrl+C
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(ForwarderExit::Quit);
+                }
+                KeyCode::Char('?') => help = !help,
+                KeyCode::Esc if help => help = false,
+                _ if help => {}
+                KeyCode::Char('q') => return Ok(ForwarderExit::Quit),
+                KeyCode::Char('p') => return Ok(ForwarderExit::Reselect),
+                KeyCode::Char('r') => reset.store(true, Ordering::Relaxed),
+                _ => {}
+            }
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    devices.sort_by(|a, b| a.path.cmp(&b.path));
-    Ok(devices)
+    #[test]
+    fn guard_restores_the_terminal_on_panic() {
+        let mut out = Vec::new();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _guard = TerminalGuard { out: &mut out };
+            panic!("forced panic inside a session");
+        }));
+        assert!(result.is_err());
+        let mut expected = Vec::new();
+        execute!(expected, LeaveAlternateScreen, cursor::Show).unwrap();
+        assert_eq!(out, expected);
+    }
 }
@@ -0,0 +1,103 @@
+use std::{
+    io::{self, Write},
+    os::fd::{AsRawFd, RawFd},
+};
+
+use evdev::{
+    AttributeSet, EventType, InputEvent, KeyCode, RelativeAxisCode, uinput::VirtualDevice,
+};
+
+use crate::report::REPORT_LEN;
+
+// Name of the virtual mouse, as shown by evtest and libinput.
+const DEVICE_NAME: &str = "hidex virtual mouse";
+
+// Report button bits in order, as the kernel's HID driver maps them.
+const BUTTONS: [KeyCode; 8] = [
+    KeyCode::BTN_LEFT,
+    KeyCode::BTN_RIGHT,
+    KeyCode::BTN_MIDDLE,
+    KeyCode::BTN_SIDE,
+    KeyCode::BTN_EXTRA,
+    KeyCode::BTN_FORWARD,
+    KeyCode::BTN_BACK,
+    KeyCode::BTN_TASK,
+];
+
+const AXES: [RelativeAxisCode; 4] = [
+    RelativeAxisCode::REL_X,
+    RelativeAxisCode::REL_Y,
+    RelativeAxisCode::REL_WHEEL,
+    RelativeAxisCode::REL_HWHEEL,
+];
+
+/* Report sink that plays reports back on a local uinput mouse instead of
+ * sending them to a host, so the translation can be tried on any Linux
+ * machine. Each write must hold whole reports.
+ */
+pub struct UinputSink {
+    device: VirtualDevice,
+    // Buttons of the previous report, to send only the changes.
+    buttons: u8,
+}
+
+impl UinputSink {
+    pub fn open() -> io::Result<Self> {
+        let device = VirtualDevice::builder()?
+            .name(DEVICE_NAME)
+            .with_keys(&BUTTONS.into_iter().collect::<AttributeSet<_>>())?
+            .with_relative_axes(&AXES.into_iter().collect::<AttributeSet<_>>())?
+            .build()?;
+        Ok(Self { device, buttons: 0 })
+    }
+
+    fn play(&mut self, report: &[u8]) -> io::Result<()> {
+        let mut events = Vec::new();
+        for (bit, key) in BUTTONS.into_iter().enumerate() {
+            let mask = 1 << bit;
+            if (report[0] ^ self.buttons) & mask != 0 {
+                let pressed = report[0] & mask != 0;
+                events.push(InputEvent::new(EventType::KEY.0, key.0, pressed as i32));
+            }
+        }
+        for (axis, &byte) in AXES.into_iter().zip(&report[1..]) {
+            if byte != 0 {
+                events.push(InputEvent::new(
+                    EventType::RELATIVE.0,
+                    axis.0,
+                    byte as i8 as i32,
+                ));
+            }
+        }
+        self.buttons = report[0];
+        if events.is_empty() {
+            return Ok(());
+        }
+        self.device.emit(&events)
+    }
+}
+
+impl Write for UinputSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.len().is_multiple_of(REPORT_LEN) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "uinput output takes whole reports",
+            ));
+        }
+        for report in buf.chunks(REPORT_LEN) {
+            self.play(report)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsRawFd for UinputSink {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}